@@ -0,0 +1,495 @@
+use proc_macro2::{TokenStream, TokenTree};
+use syn::visit::{self, Visit};
+use syn::{File, Item};
+
+use crate::snippet::Snippet;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Name of a top level item. `impl` and `use` don't define a name.
+pub fn item_name(item: &Item) -> Option<String> {
+    match item {
+        Item::Const(ref x) => Some(x.ident.to_string()),
+        Item::Enum(ref x) => Some(x.ident.to_string()),
+        Item::Fn(ref x) => Some(x.sig.ident.to_string()),
+        Item::Macro(ref x) => x.ident.as_ref().map(|i| i.to_string()),
+        Item::Macro2(ref x) => Some(x.ident.to_string()),
+        Item::Mod(ref x) => Some(x.ident.to_string()),
+        Item::Static(ref x) => Some(x.ident.to_string()),
+        Item::Struct(ref x) => Some(x.ident.to_string()),
+        Item::Trait(ref x) => Some(x.ident.to_string()),
+        Item::TraitAlias(ref x) => Some(x.ident.to_string()),
+        Item::Type(ref x) => Some(x.ident.to_string()),
+        Item::Union(ref x) => Some(x.ident.to_string()),
+        _ => None,
+    }
+}
+
+// Names defined by top level items.
+pub fn defined_names(file: &File) -> BTreeSet<String> {
+    file.items.iter().filter_map(item_name).collect()
+}
+
+// Identifiers referenced by an item or a file.
+#[derive(Default, Debug)]
+pub struct References {
+    // Items named by paths (including types and traits) and identifiers in macro invocations
+    pub idents: BTreeSet<String>,
+    // Names of called methods. e.g. `sq` for `a.sq()`
+    pub methods: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct IdentCollector {
+    refs: References,
+    // Names bound by patterns and parameters in nested scopes.
+    // A single segment path bound here is a local variable rather than a reference.
+    scopes: Vec<BTreeSet<String>>,
+}
+
+impl IdentCollector {
+    fn is_local(&self, ident: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(ident))
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(BTreeSet::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    // Macro arguments are not parsed, so take all identifiers in them.
+    fn collect_tokens(&mut self, tokens: TokenStream) {
+        for tok in tokens {
+            match tok {
+                TokenTree::Ident(ref ident) => {
+                    let ident = ident.to_string();
+                    if !self.is_local(&ident) {
+                        self.refs.idents.insert(ident);
+                    }
+                }
+                TokenTree::Group(ref g) => self.collect_tokens(g.stream()),
+                _ => (),
+            }
+        }
+    }
+}
+
+// The segment of a path which can name a snippet item.
+// e.g. `ModInt` for `ModInt::new` and `gcd` for `crate::gcd`.
+// Paths into other crates (e.g. `std::io::stdin` or `::rand::random`) have none.
+fn path_item(path: &syn::Path) -> Option<String> {
+    if path.leading_colon.is_some() {
+        return None;
+    }
+    let mut segments = path.segments.iter().map(|seg| seg.ident.to_string());
+    let first = segments.next()?;
+    match first.as_str() {
+        "std" | "core" | "alloc" => None,
+        "crate" | "self" | "super" => segments.find(|s| s != "self" && s != "super"),
+        _ => Some(first),
+    }
+}
+
+impl<'ast> Visit<'ast> for IdentCollector {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if let Some(item) = path_item(path) {
+            let is_local = path.segments.len() == 1 && self.is_local(&item);
+            if !is_local {
+                self.refs.idents.insert(item);
+            }
+        }
+        visit::visit_path(self, path);
+    }
+
+    // Parameters and `let` bindings. The binding is visible until the end of the innermost scope.
+    fn visit_pat_ident(&mut self, pat: &'ast syn::PatIdent) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(pat.ident.to_string());
+        }
+        visit::visit_pat_ident(self, pat);
+    }
+
+    // The initializer is evaluated before the binding. e.g. `let n = n + 1;`
+    fn visit_local(&mut self, local: &'ast syn::Local) {
+        for attr in &local.attrs {
+            self.visit_attribute(attr);
+        }
+        if let Some((_, ref init)) = local.init {
+            self.visit_expr(init);
+        }
+        self.visit_pat(&local.pat);
+    }
+
+    fn visit_block(&mut self, block: &'ast syn::Block) {
+        self.scoped(|v| visit::visit_block(v, block));
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        self.scoped(|v| visit::visit_item_fn(v, item));
+    }
+
+    fn visit_impl_item_method(&mut self, item: &'ast syn::ImplItemMethod) {
+        self.scoped(|v| visit::visit_impl_item_method(v, item));
+    }
+
+    fn visit_trait_item_method(&mut self, item: &'ast syn::TraitItemMethod) {
+        self.scoped(|v| visit::visit_trait_item_method(v, item));
+    }
+
+    fn visit_expr_closure(&mut self, closure: &'ast syn::ExprClosure) {
+        self.scoped(|v| visit::visit_expr_closure(v, closure));
+    }
+
+    fn visit_arm(&mut self, arm: &'ast syn::Arm) {
+        self.scoped(|v| visit::visit_arm(v, arm));
+    }
+
+    // Bindings of `if let` and `while let`
+    fn visit_expr_if(&mut self, expr: &'ast syn::ExprIf) {
+        self.scoped(|v| visit::visit_expr_if(v, expr));
+    }
+
+    fn visit_expr_while(&mut self, expr: &'ast syn::ExprWhile) {
+        self.scoped(|v| visit::visit_expr_while(v, expr));
+    }
+
+    fn visit_expr_for_loop(&mut self, expr: &'ast syn::ExprForLoop) {
+        self.visit_expr(&expr.expr);
+        self.scoped(|v| {
+            v.visit_pat(&expr.pat);
+            v.visit_block(&expr.body);
+        });
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast syn::ExprMethodCall) {
+        self.refs.methods.insert(call.method.to_string());
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        self.collect_tokens(mac.tokens.clone());
+        visit::visit_macro(self, mac);
+    }
+}
+
+pub fn file_references(file: &File) -> References {
+    let mut collector = IdentCollector::default();
+    collector.visit_file(file);
+    collector.refs
+}
+
+pub fn item_references(item: &Item) -> References {
+    let mut collector = IdentCollector::default();
+    collector.visit_item(item);
+    collector.refs
+}
+
+// Identifiers referenced by paths, types, traits and macro invocations in `file`.
+pub fn referenced_idents(file: &File) -> BTreeSet<String> {
+    file_references(file).idents
+}
+
+// Infer dependencies between snippets from identifier usage.
+// If a snippet refers to an identifier which is not defined in itself but defined by another snippet,
+// the latter is an implicit dependency. Explicit dependencies are not included in the result.
+pub fn infer_dependencies(snips: &[Snippet]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut defined: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mut referenced: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mut explicit: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+
+    for snip in snips {
+        let file = match syn::parse_file(&snip.content) {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Failed to analyze snippet {:?}: {}", &snip.attrs.names, e);
+                continue;
+            }
+        };
+        let defs = defined_names(&file);
+        let refs = referenced_idents(&file);
+
+        for name in &snip.attrs.names {
+            defined
+                .entry(name)
+                .or_default()
+                .extend(defs.iter().cloned());
+            referenced
+                .entry(name)
+                .or_default()
+                .extend(refs.iter().cloned());
+            explicit
+                .entry(name)
+                .or_default()
+                .extend(snip.attrs.uses.iter().map(|s| s.as_str()));
+        }
+    }
+
+    // identifier -> snippet names which define it
+    let mut providers: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, defs) in &defined {
+        for def in defs {
+            providers.entry(def).or_default().push(name);
+        }
+    }
+    for candidates in providers.values_mut() {
+        // Prefer the smallest snippet.
+        candidates.sort_by_key(|name| (defined[name].len(), *name));
+    }
+
+    let mut res = BTreeMap::new();
+    for (name, refs) in &referenced {
+        let defs = &defined[name];
+        let uses = &explicit[name];
+        for ident in refs.iter().filter(|i| !defs.contains(*i)) {
+            if let Some(candidates) = providers.get(ident.as_str()) {
+                if candidates.iter().any(|c| uses.contains(c)) {
+                    continue;
+                }
+                let dep = candidates
+                    .iter()
+                    .find(|c| **c == ident.as_str())
+                    .unwrap_or(&candidates[0]);
+                res.entry(name.to_string())
+                    .or_insert_with(BTreeSet::new)
+                    .insert(dep.to_string());
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::infer_dependencies;
+    use crate::parser::{parse_snippet, ParseOptions};
+    use crate::snippet::process_snippets;
+    use crate::writer::format_src;
+
+    #[test]
+    fn test_infer_dependencies() {
+        let src = r#"
+            #[snippet("mymath")]
+            #[snippet]
+            fn gcd(a: u64, b: u64) -> u64 {
+                if b == 0 { a } else { gcd(b, a % b) }
+            }
+
+            #[snippet("mymath")]
+            #[snippet]
+            fn lcm(a: u64, b: u64) -> u64 {
+                a / gcd(a, b) * b
+            }
+
+            #[snippet]
+            struct Edge(usize);
+
+            #[snippet("chmin")]
+            macro_rules! chmin {
+                ($a: expr, $b: expr) => { if $a > $b { $a = $b; } };
+            }
+
+            #[snippet]
+            fn graph(e: Vec<Edge>) -> usize {
+                let mut x = 0;
+                chmin!(x, lcm(1, 2));
+                x
+            }
+
+            #[snippet(include = "gcd")]
+            fn gcd_list(list: &[u64]) -> u64 {
+                list.iter().fold(list[0], |a, &b| gcd(a, b))
+            }
+        "#;
+
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        let inferred = infer_dependencies(&snips);
+
+        assert_eq!(
+            inferred
+                .get("lcm")
+                .map(|s| s.iter().cloned().collect::<Vec<_>>()),
+            Some(vec!["gcd".to_string()])
+        );
+        // gcd is defined in mymath itself.
+        assert_eq!(inferred.get("mymath"), None);
+        assert_eq!(
+            inferred
+                .get("graph")
+                .map(|s| s.iter().cloned().collect::<Vec<_>>()),
+            Some(vec![
+                "Edge".to_string(),
+                "chmin".to_string(),
+                "lcm".to_string()
+            ])
+        );
+        // Explicit dependency
+        assert_eq!(inferred.get("gcd_list"), None);
+
+        let snip = process_snippets(&snips, &inferred);
+        assert_eq!(
+            format_src(snip["lcm"].as_str()).unwrap(),
+            format_src(
+                "fn gcd(a: u64, b: u64) -> u64 { if b == 0 { a } else { gcd(b, a % b) } } fn lcm(a: u64, b: u64) -> u64 { a / gcd(a, b) * b }"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_infer_dependencies_locals() {
+        let src = r#"
+            #[snippet]
+            fn gcd(a: u64, b: u64) -> u64 {
+                if b == 0 { a } else { gcd(b, a % b) }
+            }
+
+            #[snippet]
+            fn lcm(a: u64, b: u64) -> u64 {
+                a * b
+            }
+
+            #[snippet]
+            fn modulo() -> u64 { 7 }
+
+            #[snippet]
+            fn params(gcd: u64, lcm: u64) -> u64 {
+                let f = |modulo: u64| modulo + 1;
+                f(gcd + lcm)
+            }
+
+            #[snippet]
+            fn bindings(x: Option<u64>) -> u64 {
+                let lcm = 3;
+                let gcd = match x {
+                    Some(modulo) => modulo,
+                    None => lcm,
+                };
+                for gcd in 0..gcd {
+                    println!("{}", gcd);
+                }
+                gcd
+            }
+
+            #[snippet]
+            fn shadowed(x: Option<u64>) -> u64 {
+                if let Some(lcm) = x {
+                    return lcm;
+                }
+                // The initializer refers to the function.
+                let gcd = gcd(4, 6);
+                { let modulo = 3; }
+                gcd + lcm(modulo(), 2)
+            }
+        "#;
+
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        let inferred = infer_dependencies(&snips);
+
+        assert_eq!(inferred.get("params"), None);
+        assert_eq!(inferred.get("bindings"), None);
+        assert_eq!(
+            inferred
+                .get("shadowed")
+                .map(|s| s.iter().cloned().collect::<Vec<_>>()),
+            Some(vec![
+                "gcd".to_string(),
+                "lcm".to_string(),
+                "modulo".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_infer_dependencies_providers() {
+        let src = r#"
+            #[snippet("big")]
+            fn modpow() {}
+
+            #[snippet("big")]
+            #[snippet("tiny")]
+            fn inv() {}
+
+            #[snippet("small")]
+            fn modpow() {}
+
+            #[snippet("inv")]
+            fn inv() {}
+
+            #[snippet("inv")]
+            fn inv_helper() {}
+
+            #[snippet]
+            fn solve() {
+                modpow();
+                inv();
+            }
+
+            #[snippet(include = "big")]
+            fn explicit() {
+                modpow();
+            }
+
+            fn main() {
+                snippet_block! { name = "read", {
+                    let x = inv();
+                }}
+            }
+        "#;
+
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        let inferred = infer_dependencies(&snips);
+
+        // The smallest snippet unless one is named after the identifier
+        assert_eq!(
+            inferred
+                .get("solve")
+                .map(|s| s.iter().cloned().collect::<Vec<_>>()),
+            Some(vec!["inv".to_string(), "small".to_string()])
+        );
+        // Any snippet which defines it is enough.
+        assert_eq!(inferred.get("explicit"), None);
+        // Statements can't be analyzed.
+        assert_eq!(inferred.get("read"), None);
+    }
+
+    #[test]
+    fn test_infer_dependencies_paths() {
+        let src = r#"
+            #[snippet]
+            fn io() {}
+
+            #[snippet]
+            fn new() {}
+
+            #[snippet]
+            fn random() {}
+
+            #[snippet]
+            fn gcd() {}
+
+            #[snippet]
+            struct ModInt(u64);
+
+            #[snippet]
+            fn solve() {
+                let mut s = String::new();
+                std::io::stdin().read_line(&mut s).unwrap();
+                let _ = core::mem::size_of::<ModInt>();
+                let _: u64 = ::rand::random();
+                let _ = ModInt::new(1);
+                crate::gcd();
+            }
+        "#;
+
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        let inferred = infer_dependencies(&snips);
+
+        // Only the first item of a path is a reference, and paths into other crates are not.
+        assert_eq!(
+            inferred
+                .get("solve")
+                .map(|s| s.iter().cloned().collect::<Vec<_>>()),
+            Some(vec!["ModInt".to_string(), "gcd".to_string()])
+        );
+    }
+}
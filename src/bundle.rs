@@ -0,0 +1,384 @@
+use syn::spanned::Spanned;
+use syn::{parse_file, Item, UseTree};
+
+use crate::analysis::file_references;
+use crate::prune;
+use crate::snippet::{Resolution, SnippetGraph};
+use crate::source::{Edit, Source};
+
+// A solution file with snippets expanded.
+#[derive(Debug)]
+pub struct Bundle {
+    pub code: String,
+    // Snippets in the order they are emitted.
+    pub resolution: Resolution,
+    // Imported names which are not found in snippets.
+    pub unresolved: Vec<String>,
+    // Messages for the user. e.g. a glob import expanded to every snippet
+    pub warnings: Vec<String>,
+}
+
+// An item imported from the snippet library.
+struct Import {
+    name: String,
+    rename: Option<String>,
+}
+
+// Collect imported leaf names of a `use` tree. Returns false if it has a glob import.
+fn collect_imports(tree: &UseTree, res: &mut Vec<Import>) -> bool {
+    match tree {
+        UseTree::Path(ref path) => collect_imports(&path.tree, res),
+        UseTree::Name(ref name) => {
+            res.push(Import {
+                name: name.ident.to_string(),
+                rename: None,
+            });
+            true
+        }
+        UseTree::Rename(ref rename) => {
+            res.push(Import {
+                name: rename.ident.to_string(),
+                rename: Some(rename.rename.to_string()),
+            });
+            true
+        }
+        UseTree::Glob(_) => false,
+        UseTree::Group(ref group) => {
+            let mut ok = true;
+            for tree in &group.items {
+                ok &= collect_imports(tree, res);
+            }
+            ok
+        }
+    }
+}
+
+fn root_ident(tree: &UseTree) -> Option<String> {
+    match tree {
+        UseTree::Path(ref path) => Some(path.ident.to_string()),
+        UseTree::Name(ref name) => Some(name.ident.to_string()),
+        _ => None,
+    }
+}
+
+// Expand snippets imported by `use <crate_name>::...;` in `src`.
+// A glob import expands every snippet, so it's better combined with `prune`.
+// The `use` declarations are removed and the snippets (with their dependencies) are appended to the end of the file.
+// If `prune` is true, items which are not reachable from the solution are removed from the snippets.
+// `format` is applied to the expanded snippets.
+pub fn bundle(
+    src: &str,
+    crate_names: &[String],
+    graph: &SnippetGraph,
+    prune: bool,
+    format: impl Fn(&str) -> Option<String>,
+) -> Result<Bundle, syn::parse::Error> {
+    let file = parse_file(src)?;
+    let source = Source::new(src);
+
+    let mut edits = Vec::new();
+    let mut imports = Vec::new();
+    let mut warnings = Vec::new();
+    let mut glob = false;
+    for item in &file.items {
+        let is_library =
+            |ident: Option<String>| matches!(ident, Some(ref i) if crate_names.contains(i));
+        match item {
+            Item::Use(ref item_use) if is_library(root_ident(&item_use.tree)) => {
+                let mut found = Vec::new();
+                if !collect_imports(&item_use.tree, &mut found) {
+                    warnings.push(format!(
+                        "glob import `{}` is expanded to every snippet",
+                        &src[source.range(item.span())]
+                    ));
+                    glob = true;
+                }
+                let renames = found
+                    .iter()
+                    .filter_map(|import| {
+                        import
+                            .rename
+                            .as_ref()
+                            .map(|r| format!("use self::{} as {};\n", import.name, r))
+                    })
+                    .collect::<String>();
+                let range = source.removal_range(source.range(item.span()));
+                edits.push(Edit {
+                    range,
+                    replacement: renames,
+                });
+                imports.extend(found);
+            }
+            Item::ExternCrate(ref extern_crate)
+                if is_library(Some(extern_crate.ident.to_string())) =>
+            {
+                edits.push(Edit::remove(
+                    source.removal_range(source.range(item.span())),
+                ));
+            }
+            _ => (),
+        }
+    }
+
+    let mut unresolved = Vec::new();
+    let mut roots = Vec::new();
+    for import in &imports {
+        if graph.names().any(|n| *n == import.name) {
+            if !roots.contains(&import.name.as_str()) {
+                roots.push(import.name.as_str());
+            }
+        } else {
+            unresolved.push(import.name.clone());
+        }
+    }
+
+    if glob {
+        for name in graph.names() {
+            if !roots.contains(&name.as_str()) {
+                roots.push(name.as_str());
+            }
+        }
+    }
+
+    let resolution = graph.resolve(roots);
+    let mut snippets = graph.concat(&resolution.order);
+    if prune {
+        // Imported names are kept even if they are not referenced by a path.
+        let mut used = file_references(&file);
+        used.idents
+            .extend(imports.iter().map(|import| import.name.clone()));
+        snippets = prune::prune(&snippets, &used)?;
+    }
+
+    let mut code = source.slice_with_edits(0..src.len(), &edits);
+    if !snippets.is_empty() {
+        if !code.ends_with('\n') {
+            code.push('\n');
+        }
+        code.push('\n');
+        code += &format(&snippets).unwrap_or(snippets);
+    }
+
+    Ok(Bundle {
+        code,
+        resolution,
+        unresolved,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::bundle;
+    use crate::parser::{parse_snippet, ParseOptions};
+    use crate::snippet::SnippetGraph;
+    use crate::writer::format_src;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_bundle() {
+        let lib = r#"
+            #[snippet]
+            fn gcd(a: u64, b: u64) -> u64 {
+                if b == 0 { a } else { gcd(b, a % b) }
+            }
+
+            #[snippet(include = "gcd")]
+            fn lcm(a: u64, b: u64) -> u64 {
+                a / gcd(a, b) * b
+            }
+
+            #[snippet]
+            struct SegTree;
+        "#;
+
+        let snips = parse_snippet(lib, None, &ParseOptions::default()).0;
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+
+        let main = r#"use std::io;
+use mysnippet::{lcm, SegTree as Seg, gcd};
+use mysnippet::math::unknown;
+
+fn main() {
+    // comment
+    println!("{}", lcm(2, 3));
+}
+"#;
+
+        let bundled = bundle(main, &["mysnippet".to_string()], &graph, false, format_src).unwrap();
+        assert_eq!(bundled.resolution.order, vec!["gcd", "lcm", "SegTree"]);
+        assert_eq!(bundled.unresolved, vec!["unknown"]);
+        assert_eq!(
+            bundled.code,
+            r#"use std::io;
+use self::SegTree as Seg;
+
+fn main() {
+    // comment
+    println!("{}", lcm(2, 3));
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+struct SegTree;
+"#
+        );
+
+        // Unused items of a snippet are removed.
+        let lib = r#"
+            #[snippet("lcm")]
+            fn gcd(a: u64, b: u64) -> u64 {
+                if b == 0 { a } else { gcd(b, a % b) }
+            }
+            #[snippet("lcm")]
+            fn lcm(a: u64, b: u64) -> u64 {
+                a / gcd(a, b) * b
+            }
+            #[snippet("lcm")]
+            fn modpow(x: u64, n: u64, m: u64) -> u64 {
+                if n == 0 { 1 } else { x * modpow(x, n - 1, m) % m }
+            }
+            #[snippet("lcm")]
+            trait Zero {
+                fn zero() -> Self;
+            }
+            #[snippet("lcm")]
+            impl Zero for u64 {
+                fn zero() -> Self { 0 }
+            }
+        "#;
+        let snips = parse_snippet(lib, None, &ParseOptions::default()).0;
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+        let main = r#"use mysnippet::lcm;
+
+fn main() {
+    println!("{}", lcm(2, 3));
+}
+"#;
+        let bundled = bundle(main, &["mysnippet".to_string()], &graph, true, format_src).unwrap();
+        assert_eq!(bundled.resolution.order, vec!["lcm"]);
+        assert_eq!(
+            bundled.code,
+            r#"
+fn main() {
+    println!("{}", lcm(2, 3));
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_bundle_prune_method_call() {
+        let lib = r#"
+            #[snippet("ext")]
+            trait Sq {
+                fn sq(self) -> Self;
+            }
+            #[snippet("ext")]
+            impl Sq for u64 {
+                fn sq(self) -> Self { self * self }
+            }
+            #[snippet("ext")]
+            trait Cube {
+                fn cube(self) -> Self;
+            }
+            #[snippet("ext")]
+            impl Cube for u64 {
+                fn cube(self) -> Self { self * self * self }
+            }
+        "#;
+        let snips = parse_snippet(lib, None, &ParseOptions::default()).0;
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+        let main = r#"use mysnippet::ext;
+
+fn main() {
+    let a: u64 = 3;
+    let b = a.sq();
+}
+"#;
+        let bundled = bundle(main, &["mysnippet".to_string()], &graph, true, format_src).unwrap();
+        assert_eq!(
+            bundled.code,
+            r#"
+fn main() {
+    let a: u64 = 3;
+    let b = a.sq();
+}
+
+trait Sq {
+    fn sq(self) -> Self;
+}
+impl Sq for u64 {
+    fn sq(self) -> Self {
+        self * self
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_bundle_imports() {
+        let lib = r#"
+            #[snippet]
+            fn a() {}
+
+            #[snippet]
+            fn b() {}
+        "#;
+        let snips = parse_snippet(lib, None, &ParseOptions::default()).0;
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+        let crate_names = ["lib".to_string(), "lib2".to_string()];
+
+        // Other crates are left. The file doesn't end with a newline.
+        let main = "extern crate lib;\nuse other::b;\nuse lib2::{a, a as x};\nfn main() {}";
+        let bundled = bundle(main, &crate_names, &graph, false, |_| None).unwrap();
+        assert_eq!(bundled.resolution.order, vec!["a"]);
+        assert!(bundled.unresolved.is_empty());
+        assert!(bundled.warnings.is_empty());
+        // Snippets are left unformatted if formatting fails.
+        assert_eq!(
+            bundled.code,
+            format!(
+                "use other::b;\nuse self::a as x;\nfn main() {{}}\n\n{}",
+                graph.concat(&bundled.resolution.order)
+            )
+        );
+
+        // A glob import expands every snippet.
+        let main = "use lib::*;\nfn main() {\n    b();\n}\n";
+        let bundled = bundle(main, &crate_names, &graph, true, format_src).unwrap();
+        assert_eq!(bundled.resolution.order, vec!["a", "b"]);
+        assert_eq!(
+            bundled.warnings,
+            vec!["glob import `use lib::*;` is expanded to every snippet"]
+        );
+        assert_eq!(bundled.code, "fn main() {\n    b();\n}\n\nfn b() {}\n");
+
+        let main = "use std::io;\nfn main() {}\n";
+        let bundled = bundle(main, &crate_names, &graph, true, format_src).unwrap();
+        assert!(bundled.resolution.order.is_empty());
+        assert_eq!(bundled.code, main);
+
+        assert!(bundle("fn main(", &crate_names, &graph, false, format_src).is_err());
+    }
+}
@@ -0,0 +1,765 @@
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    Arm, Attribute, Block, Expr, ExprMatch, ExprStruct, Field, FieldValue, Fields, File,
+    ForeignItem, ImplItem, Item, ItemEnum, Lit, Meta, NestedMeta, Stmt, TraitItem, Variant,
+};
+
+use crate::source::{Edit, Source};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+// `ref` or `mut` specifies the kind of the reference to attributes.
+macro_rules! attrs_of {
+    ($node: expr, ref, $($v: path), *) => {
+        match $node {
+            $(
+                $v(x) => Some(&x.attrs),
+            )*
+            _ => None,
+        }
+    };
+    ($node: expr, mut, $($v: path), *) => {
+        match $node {
+            $(
+                $v(x) => Some(&mut x.attrs),
+            )*
+            _ => None,
+        }
+    };
+}
+
+macro_rules! item_attrs {
+    ($item: expr, $m: tt) => {
+        // All Item variants except Item::Verbatim
+        attrs_of!(
+            $item,
+            $m,
+            Item::ExternCrate,
+            Item::Use,
+            Item::Static,
+            Item::Const,
+            Item::Fn,
+            Item::Mod,
+            Item::ForeignMod,
+            Item::Type,
+            Item::Struct,
+            Item::Enum,
+            Item::Union,
+            Item::Trait,
+            Item::Impl,
+            Item::Macro,
+            Item::Macro2
+        )
+    };
+}
+
+macro_rules! impl_item_attrs {
+    ($item: expr, $m: tt) => {
+        attrs_of!(
+            $item,
+            $m,
+            ImplItem::Const,
+            ImplItem::Method,
+            ImplItem::Type,
+            ImplItem::Macro
+        )
+    };
+}
+
+macro_rules! trait_item_attrs {
+    ($item: expr, $m: tt) => {
+        attrs_of!(
+            $item,
+            $m,
+            TraitItem::Const,
+            TraitItem::Method,
+            TraitItem::Type,
+            TraitItem::Macro
+        )
+    };
+}
+
+macro_rules! foreign_item_attrs {
+    ($item: expr, $m: tt) => {
+        attrs_of!(
+            $item,
+            $m,
+            ForeignItem::Fn,
+            ForeignItem::Static,
+            ForeignItem::Type,
+            ForeignItem::Macro
+        )
+    };
+}
+
+// Expressions which are likely to have attributes as statements. e.g. `#[cfg(debug)] eprintln!(...);`
+macro_rules! expr_attrs {
+    ($expr: expr, $m: tt) => {
+        attrs_of!(
+            $expr,
+            $m,
+            Expr::Assign,
+            Expr::AssignOp,
+            Expr::Block,
+            Expr::Call,
+            Expr::ForLoop,
+            Expr::If,
+            Expr::Loop,
+            Expr::Macro,
+            Expr::Match,
+            Expr::MethodCall,
+            Expr::Unsafe,
+            Expr::While
+        )
+    };
+}
+
+fn stmt_attrs(stmt: &Stmt) -> Option<&Vec<Attribute>> {
+    match stmt {
+        Stmt::Local(ref local) => Some(&local.attrs),
+        Stmt::Item(ref item) => item_attrs!(item, ref),
+        Stmt::Expr(ref expr) | Stmt::Semi(ref expr, _) => expr_attrs!(expr, ref),
+    }
+}
+
+fn stmt_attrs_mut(stmt: &mut Stmt) -> Option<&mut Vec<Attribute>> {
+    match stmt {
+        Stmt::Local(ref mut local) => Some(&mut local.attrs),
+        Stmt::Item(ref mut item) => item_attrs!(item, mut),
+        Stmt::Expr(ref mut expr) | Stmt::Semi(ref mut expr, _) => expr_attrs!(expr, mut),
+    }
+}
+
+// Content of `#[...]`. e.g. `cfg(test)`
+fn attr_content(attr: &Attribute) -> TokenStream {
+    let mut tokens = attr.path.to_token_stream();
+    tokens.extend(attr.tokens.clone());
+    tokens
+}
+
+fn is_cfg_path(attr: &Attribute) -> bool {
+    attr.path.is_ident("cfg") || attr.path.is_ident("cfg_attr")
+}
+
+// Split tokens by commas at the top level. An empty part after a trailing comma is dropped.
+fn split_commas(tokens: TokenStream) -> Vec<TokenStream> {
+    let mut parts = vec![TokenStream::new()];
+    for tok in tokens {
+        match tok {
+            TokenTree::Punct(ref p) if p.as_char() == ',' => parts.push(TokenStream::new()),
+            tok => parts.last_mut().unwrap().extend(Some(tok)),
+        }
+    }
+    if matches!(parts.last(), Some(p) if p.is_empty()) {
+        parts.pop();
+    }
+    parts
+}
+
+// Enabled configuration options given by `--features` and `--cfg`.
+// `cfg` predicates are evaluated only with them. Options not given are disabled, e.g. `test` and `unix`.
+#[derive(Debug, Default, Clone)]
+pub struct Cfg {
+    // e.g. `unix`
+    names: BTreeSet<String>,
+    // e.g. `feature = "foo"` and `target_os = "linux"`
+    pairs: BTreeSet<(String, String)>,
+}
+
+impl Cfg {
+    // `features` are separated by commas or spaces as cargo does.
+    // `cfgs` are `name` or `name="value"`.
+    pub fn new<'a>(
+        features: impl IntoIterator<Item = &'a str>,
+        cfgs: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut cfg = Cfg::default();
+        for feature in features
+            .into_iter()
+            .flat_map(|s| s.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|s| !s.is_empty())
+        {
+            cfg.pairs
+                .insert(("feature".to_string(), feature.to_string()));
+        }
+        for spec in cfgs {
+            match spec.split_once('=') {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value);
+                    cfg.pairs
+                        .insert((name.trim().to_string(), value.to_string()));
+                }
+                None => {
+                    cfg.names.insert(spec.trim().to_string());
+                }
+            }
+        }
+        cfg
+    }
+
+    fn eval(&self, pred: &NestedMeta) -> bool {
+        let meta = match pred {
+            NestedMeta::Meta(ref meta) => meta,
+            NestedMeta::Lit(_) => return false,
+        };
+        match meta {
+            Meta::Path(ref path) => {
+                matches!(path.get_ident(), Some(ident) if self.names.contains(&ident.to_string()))
+            }
+            Meta::NameValue(ref nv) => match (nv.path.get_ident(), &nv.lit) {
+                (Some(ident), Lit::Str(ref value)) => {
+                    self.pairs.contains(&(ident.to_string(), value.value()))
+                }
+                _ => false,
+            },
+            Meta::List(ref list) => {
+                let mut nested = list.nested.iter();
+                if list.path.is_ident("all") {
+                    nested.all(|p| self.eval(p))
+                } else if list.path.is_ident("any") {
+                    nested.any(|p| self.eval(p))
+                } else if list.path.is_ident("not") && list.nested.len() == 1 {
+                    !self.eval(&list.nested[0])
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    // Evaluate the content of an attribute.
+    // Returns `None` for `cfg` with a false predicate, which makes the node inactive.
+    // Otherwise returns the contents of attributes to be left. `cfg_attr` is expanded recursively.
+    fn expand(&self, content: TokenStream) -> Option<Vec<TokenStream>> {
+        let tokens = content.clone().into_iter().collect::<Vec<_>>();
+        let (name, group) = match tokens.as_slice() {
+            [TokenTree::Ident(ref name), TokenTree::Group(ref group)]
+                if group.delimiter() == Delimiter::Parenthesis =>
+            {
+                (name.to_string(), group)
+            }
+            _ => return Some(vec![content]),
+        };
+        let mut parts = split_commas(group.stream()).into_iter();
+        let pred = match parts.next().map(syn::parse2::<NestedMeta>) {
+            Some(Ok(pred)) => pred,
+            // Leave it to the compiler
+            _ => return Some(vec![content]),
+        };
+        match name.as_str() {
+            "cfg" if self.eval(&pred) => Some(Vec::new()),
+            "cfg" => None,
+            "cfg_attr" if self.eval(&pred) => {
+                let mut res = Vec::new();
+                for part in parts {
+                    res.extend(self.expand(part)?);
+                }
+                Some(res)
+            }
+            "cfg_attr" => Some(Vec::new()),
+            _ => Some(vec![content]),
+        }
+    }
+
+    // Drop inactive items (and other nodes) in `file` and strip `cfg` attributes of active ones.
+    // Returns `None` if the whole file is inactive.
+    // Also returns edits for the original source text to do the same, which are used for `verbatim`.
+    pub fn strip_file(&self, source: &Source, file: &mut File) -> Option<Vec<Edit>> {
+        let mut edits = self.attr_edits(source, &file.attrs)?;
+        edits.extend(node_edits(self, source, |v| v.visit_file(file)));
+
+        self.process(&mut file.attrs);
+        strip_nodes(self, |v| v.visit_file_mut(file));
+        Some(edits)
+    }
+}
+
+impl NodeFilter for Cfg {
+    // Expand `cfg_attr` and remove `cfg` in `attrs`. Returns false if the node is inactive.
+    fn process(&self, attrs: &mut Vec<Attribute>) -> bool {
+        let mut res = Vec::new();
+        for attr in attrs.drain(..) {
+            if !is_cfg_path(&attr) {
+                res.push(attr);
+                continue;
+            }
+            let expanded = match self.expand(attr_content(&attr)) {
+                Some(expanded) => expanded,
+                None => return false,
+            };
+            for content in expanded {
+                // `#`, `!` and brackets keep the original spans.
+                let parsed: Attribute = syn::parse_quote!(#[#content]);
+                let mut new = attr.clone();
+                new.path = parsed.path;
+                new.tokens = parsed.tokens;
+                res.push(new);
+            }
+        }
+        *attrs = res;
+        true
+    }
+
+    fn attr_edits(&self, source: &Source, attrs: &[Attribute]) -> Option<Vec<Edit>> {
+        let mut edits = Vec::new();
+        for attr in attrs.iter().filter(|attr| is_cfg_path(attr)) {
+            let expanded = self.expand(attr_content(attr))?;
+            let range = source.range(attr.span());
+            if expanded.is_empty() {
+                edits.push(Edit::remove(source.removal_range(range)));
+                continue;
+            }
+            let bang = match attr.style {
+                syn::AttrStyle::Inner(_) => "!",
+                syn::AttrStyle::Outer => "",
+            };
+            let replacement = expanded
+                .iter()
+                .map(|content| format!("#{}[{}]", bang, source_text(source, content)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            edits.push(Edit { range, replacement });
+        }
+        Some(edits)
+    }
+}
+
+// Original text of tokens.
+fn source_text(source: &Source, tokens: &TokenStream) -> String {
+    let mut iter = tokens.clone().into_iter();
+    let first = match iter.next() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    let last = iter.last().unwrap_or_else(|| first.clone());
+    let range = source.range(first.span()).start..source.range(last.span()).end;
+    source.text()[range].to_string()
+}
+
+// Decides which nodes (items, statements, fields, etc.) are left in the output.
+pub trait NodeFilter {
+    // Returns false if a node with `attrs` is removed. Otherwise `attrs` may be rewritten.
+    fn process(&self, attrs: &mut Vec<Attribute>) -> bool;
+
+    // Edits for the original source text to do the same as `process`. `None` if the node is removed.
+    fn attr_edits(&self, source: &Source, attrs: &[Attribute]) -> Option<Vec<Edit>>;
+
+    // Whether a statement is left regardless of its attributes.
+    fn keep_stmt(&self, _stmt: &Stmt) -> bool {
+        true
+    }
+}
+
+// Remove nodes in the syntax tree by `f`. e.g. `strip_nodes(&filter, |v| visit_mut::visit_item_mut(v, &mut item))`
+// Nodes are checked when they are in a container, so the root node passed to `f` is always left.
+pub fn strip_nodes(filter: &dyn NodeFilter, f: impl FnOnce(&mut dyn VisitMut)) {
+    f(&mut Stripper { filter });
+}
+
+// Edits to remove nodes in the original source text. `f` visits the syntax tree.
+pub fn node_edits<'ast>(
+    filter: &dyn NodeFilter,
+    source: &Source,
+    f: impl FnOnce(&mut dyn Visit<'ast>),
+) -> Vec<Edit> {
+    let mut visitor = EditVisitor {
+        filter,
+        source,
+        edits: Vec::new(),
+    };
+    f(&mut visitor);
+    visitor.edits
+}
+
+// Removes nodes from the syntax tree.
+struct Stripper<'a> {
+    filter: &'a dyn NodeFilter,
+}
+
+impl<'a> Stripper<'a> {
+    fn process(&self, attrs: Option<&mut Vec<Attribute>>) -> bool {
+        match attrs {
+            Some(attrs) => self.filter.process(attrs),
+            None => true,
+        }
+    }
+
+    fn items(&self, items: &mut Vec<Item>) {
+        items.retain_mut(|item| self.process(item_attrs!(item, mut)));
+    }
+}
+
+fn retain_punctuated<T, P: Default>(
+    punctuated: &mut Punctuated<T, P>,
+    mut f: impl FnMut(&mut T) -> bool,
+) {
+    let old = std::mem::take(punctuated);
+    let trailing = old.trailing_punct();
+    *punctuated = old
+        .into_iter()
+        .filter_map(|mut x| if f(&mut x) { Some(x) } else { None })
+        .collect();
+    if trailing && !punctuated.is_empty() {
+        punctuated.push_punct(P::default());
+    }
+}
+
+impl<'a> VisitMut for Stripper<'a> {
+    fn visit_file_mut(&mut self, file: &mut File) {
+        self.items(&mut file.items);
+        visit_mut::visit_file_mut(self, file);
+    }
+
+    fn visit_item_mod_mut(&mut self, item_mod: &mut syn::ItemMod) {
+        if let Some((_, ref mut items)) = item_mod.content {
+            self.items(items);
+        }
+        visit_mut::visit_item_mod_mut(self, item_mod);
+    }
+
+    fn visit_item_impl_mut(&mut self, item_impl: &mut syn::ItemImpl) {
+        item_impl
+            .items
+            .retain_mut(|item| self.process(impl_item_attrs!(item, mut)));
+        visit_mut::visit_item_impl_mut(self, item_impl);
+    }
+
+    fn visit_item_trait_mut(&mut self, item_trait: &mut syn::ItemTrait) {
+        item_trait
+            .items
+            .retain_mut(|item| self.process(trait_item_attrs!(item, mut)));
+        visit_mut::visit_item_trait_mut(self, item_trait);
+    }
+
+    fn visit_item_foreign_mod_mut(&mut self, foreign_mod: &mut syn::ItemForeignMod) {
+        foreign_mod
+            .items
+            .retain_mut(|item| self.process(foreign_item_attrs!(item, mut)));
+        visit_mut::visit_item_foreign_mod_mut(self, foreign_mod);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block
+            .stmts
+            .retain_mut(|stmt| self.filter.keep_stmt(stmt) && self.process(stmt_attrs_mut(stmt)));
+        visit_mut::visit_block_mut(self, block);
+    }
+
+    fn visit_fields_mut(&mut self, fields: &mut Fields) {
+        match fields {
+            Fields::Named(ref mut named) => retain_punctuated(&mut named.named, |f: &mut Field| {
+                self.process(Some(&mut f.attrs))
+            }),
+            Fields::Unnamed(ref mut unnamed) => {
+                retain_punctuated(&mut unnamed.unnamed, |f: &mut Field| {
+                    self.process(Some(&mut f.attrs))
+                })
+            }
+            Fields::Unit => (),
+        }
+        visit_mut::visit_fields_mut(self, fields);
+    }
+
+    fn visit_item_enum_mut(&mut self, item_enum: &mut ItemEnum) {
+        retain_punctuated(&mut item_enum.variants, |v: &mut Variant| {
+            self.process(Some(&mut v.attrs))
+        });
+        visit_mut::visit_item_enum_mut(self, item_enum);
+    }
+
+    fn visit_expr_match_mut(&mut self, expr: &mut ExprMatch) {
+        expr.arms
+            .retain_mut(|arm: &mut Arm| self.process(Some(&mut arm.attrs)));
+        visit_mut::visit_expr_match_mut(self, expr);
+    }
+
+    fn visit_expr_struct_mut(&mut self, expr: &mut ExprStruct) {
+        retain_punctuated(&mut expr.fields, |f: &mut FieldValue| {
+            self.process(Some(&mut f.attrs))
+        });
+        visit_mut::visit_expr_struct_mut(self, expr);
+    }
+}
+
+// Collects edits for the original source text.
+struct EditVisitor<'a, 'b> {
+    filter: &'a dyn NodeFilter,
+    source: &'b Source<'b>,
+    edits: Vec<Edit>,
+}
+
+impl<'a, 'b> EditVisitor<'a, 'b> {
+    // Returns whether a node with `attrs` is left.
+    // A removed node is removed together with the following comma if `separated`.
+    fn node(
+        &mut self,
+        attrs: Option<&Vec<Attribute>>,
+        span: proc_macro2::Span,
+        separated: bool,
+    ) -> bool {
+        let attrs = match attrs {
+            Some(attrs) => attrs,
+            None => return true,
+        };
+        match self.filter.attr_edits(self.source, attrs) {
+            Some(edits) => {
+                self.edits.extend(edits);
+                true
+            }
+            None => {
+                let mut range = self.source.range(span);
+                if separated {
+                    range = self.extend_over_comma(range);
+                }
+                self.remove(range);
+                false
+            }
+        }
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        self.edits
+            .push(Edit::remove(self.source.removal_range(range)));
+    }
+
+    fn extend_over_comma(&self, range: Range<usize>) -> Range<usize> {
+        let rest = &self.source.text()[range.end..];
+        let trimmed = rest.trim_start();
+        if trimmed.starts_with(',') {
+            range.start..range.end + (rest.len() - trimmed.len()) + 1
+        } else {
+            range
+        }
+    }
+}
+
+impl<'a, 'b, 'ast> Visit<'ast> for EditVisitor<'a, 'b> {
+    fn visit_item(&mut self, item: &'ast Item) {
+        if self.node(item_attrs!(item, ref), item.span(), false) {
+            visit::visit_item(self, item);
+        }
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast ImplItem) {
+        if self.node(impl_item_attrs!(item, ref), item.span(), false) {
+            visit::visit_impl_item(self, item);
+        }
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast TraitItem) {
+        if self.node(trait_item_attrs!(item, ref), item.span(), false) {
+            visit::visit_trait_item(self, item);
+        }
+    }
+
+    fn visit_foreign_item(&mut self, item: &'ast ForeignItem) {
+        if self.node(foreign_item_attrs!(item, ref), item.span(), false) {
+            visit::visit_foreign_item(self, item);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        if !self.filter.keep_stmt(stmt) {
+            self.remove(self.source.range(stmt.span()));
+            return;
+        }
+        // Items in statements are handled by `visit_item`.
+        let attrs = match stmt {
+            Stmt::Item(_) => None,
+            stmt => stmt_attrs(stmt),
+        };
+        if self.node(attrs, stmt.span(), false) {
+            visit::visit_stmt(self, stmt);
+        }
+    }
+
+    fn visit_field(&mut self, field: &'ast Field) {
+        if self.node(Some(&field.attrs), field.span(), true) {
+            visit::visit_field(self, field);
+        }
+    }
+
+    fn visit_variant(&mut self, variant: &'ast Variant) {
+        if self.node(Some(&variant.attrs), variant.span(), true) {
+            visit::visit_variant(self, variant);
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &'ast Arm) {
+        if self.node(Some(&arm.attrs), arm.span(), true) {
+            visit::visit_arm(self, arm);
+        }
+    }
+
+    fn visit_field_value(&mut self, field: &'ast FieldValue) {
+        if self.node(Some(&field.attrs), field.span(), true) {
+            visit::visit_field_value(self, field);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_commas, Cfg};
+    use crate::source::Source;
+    use quote::ToTokens;
+
+    fn eval(cfg: &Cfg, pred: &str) -> bool {
+        cfg.eval(&syn::parse_str(pred).unwrap())
+    }
+
+    // Contents of attributes left by `Cfg::expand`
+    fn expand(cfg: &Cfg, content: &str) -> Option<Vec<String>> {
+        let expanded = cfg.expand(content.parse().unwrap())?;
+        Some(expanded.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn test_new() {
+        let cfg = Cfg::new(
+            vec!["a,b  c", ""],
+            vec![" unix ", "target_os = \"linux\"", "k=v"],
+        );
+        for pred in &[
+            "feature = \"a\"",
+            "feature = \"b\"",
+            "feature = \"c\"",
+            "unix",
+            "target_os = \"linux\"",
+            "k = \"v\"",
+        ] {
+            assert!(eval(&cfg, pred), "{}", pred);
+        }
+        assert!(!eval(&cfg, "feature = \"\""));
+        assert!(!eval(&cfg, "test"));
+    }
+
+    #[test]
+    fn test_eval() {
+        let cfg = Cfg::new(vec![], vec!["unix"]);
+        assert!(eval(&cfg, "all()"));
+        assert!(!eval(&cfg, "any()"));
+        assert!(eval(&cfg, "not(any(test, windows))"));
+        // Malformed predicates are false.
+        assert!(!eval(&cfg, "not(unix, test)"));
+        assert!(!eval(&cfg, "unknown(unix)"));
+        assert!(!eval(&cfg, "\"unix\""));
+        assert!(!eval(&cfg, "a::unix"));
+        assert!(!eval(&cfg, "unix = 1"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let cfg = Cfg::new(vec![], vec!["unix"]);
+        assert_eq!(
+            expand(&cfg, "cfg_attr(unix, cfg_attr(unix, inline), cold,)"),
+            Some(vec!["inline".to_string(), "cold".to_string()])
+        );
+        assert_eq!(
+            expand(&cfg, "cfg_attr(windows, cfg(test))"),
+            Some(Vec::new())
+        );
+        // `cfg` in an active `cfg_attr` makes the node inactive.
+        assert_eq!(expand(&cfg, "cfg_attr(unix, cfg(test))"), None);
+        assert_eq!(expand(&cfg, "cfg(unix)"), Some(Vec::new()));
+        // Left to the compiler
+        assert_eq!(expand(&cfg, "cfg(=)"), Some(vec!["cfg (=)".to_string()]));
+        assert_eq!(expand(&cfg, "cfg"), Some(vec!["cfg".to_string()]));
+        assert_eq!(
+            expand(&cfg, "derive(Debug)"),
+            Some(vec!["derive (Debug)".to_string()])
+        );
+
+        let parts = split_commas("a, b(c, d),, e,".parse().unwrap());
+        assert_eq!(
+            parts.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec!["a", "b (c , d)", "", "e"]
+        );
+    }
+
+    #[test]
+    fn test_strip_inner_attrs() {
+        let src = "#![cfg_attr(unix, allow(dead_code), deny(unused))]\n\
+                   mod m {\n    #![cfg_attr(test, allow(unused))]\n    fn f() {}\n}\n";
+        let cfg = Cfg::new(vec![], vec!["unix"]);
+        let source = Source::new(src);
+        let mut file = syn::parse_file(src).unwrap();
+        let edits = cfg.strip_file(&source, &mut file).unwrap();
+        assert_eq!(
+            source.slice_with_edits(0..src.len(), &edits),
+            "#![allow(dead_code)] #![deny(unused)]\nmod m {\n    fn f() {}\n}\n"
+        );
+        assert_eq!(file.attrs.len(), 2);
+    }
+
+    #[test]
+    fn test_strip_file() {
+        let src = r#"#[cfg(feature = "fast")]
+fn solve() -> u64 { 1 }
+#[cfg(not(feature = "fast"))]
+fn solve() -> u64 { 2 }
+
+#[cfg_attr(unix, derive(Debug, Clone))]
+#[cfg_attr(test, derive(PartialEq))]
+struct S {
+    a: u64,
+    #[cfg(test)]
+    b: u64,
+}
+
+fn f() {
+    #[cfg(any(test, debug_assertions))]
+    eprintln!("debug");
+    let x = match 1 {
+        #[cfg(all(unix, feature = "fast"))]
+        1 => 2,
+        _ => 3,
+    };
+}
+
+#[cfg(test)]
+mod test {}
+"#;
+        let cfg = Cfg::new(vec!["fast"], vec!["unix", "target_os=\"linux\""]);
+        let source = Source::new(src);
+        let mut file = syn::parse_file(src).unwrap();
+        let edits = cfg.strip_file(&source, &mut file).unwrap();
+
+        let expected = r#"fn solve() -> u64 { 1 }
+
+#[derive(Debug, Clone)]
+struct S {
+    a: u64,
+}
+
+fn f() {
+    let x = match 1 {
+        1 => 2,
+        _ => 3,
+    };
+}
+
+"#;
+        assert_eq!(source.slice_with_edits(0..src.len(), &edits), expected);
+        assert_eq!(
+            file.into_token_stream().to_string(),
+            syn::parse_file(expected)
+                .unwrap()
+                .into_token_stream()
+                .to_string()
+        );
+
+        let src = "#![cfg(target_os = \"windows\")]\nfn f() {}\n";
+        let mut file = syn::parse_file(src).unwrap();
+        assert!(cfg.strip_file(&Source::new(src), &mut file).is_none());
+    }
+}
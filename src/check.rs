@@ -0,0 +1,252 @@
+use crate::writer::{is_statements, wrap_statements};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A snippet which doesn't compile on its own.
+#[derive(Debug)]
+pub struct Failure {
+    pub name: String,
+    // Error output of rustc
+    pub stderr: String,
+}
+
+// A temporary directory removed on drop.
+struct TempDir(PathBuf);
+
+// Distinguishes directories of concurrent checks in the same process.
+static TEMP_DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl TempDir {
+    fn new() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "cargo-snippet-check-{}-{}",
+            process::id(),
+            TEMP_DIR_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn rustc() -> String {
+    env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string())
+}
+
+// Compile `code` as a standalone library crate in `dir`. Returns rustc's error output on failure.
+fn check_src(
+    code: &str,
+    file_stem: &str,
+    edition: &str,
+    dir: &Path,
+) -> io::Result<Result<(), String>> {
+    let src_path = dir.join(format!("{}.rs", file_stem));
+    fs::write(&src_path, code)?;
+
+    let out = process::Command::new(rustc())
+        .arg(format!("--edition={}", edition))
+        .arg("--crate-type=lib")
+        .arg("--crate-name=snippet")
+        .arg("--emit=metadata")
+        .arg("--out-dir")
+        .arg(dir)
+        .args(["-A", "warnings"])
+        .arg(&src_path)
+        .output()?;
+
+    if out.status.success() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(String::from_utf8_lossy(&out.stderr).into_owned()))
+    }
+}
+
+// Compile every snippet standalone by `rustc --emit=metadata` and collect ones which fail.
+// `edition` is the edition of the snippet library. `on_checked` is called after each snippet is checked.
+pub fn check_snippets(
+    snippets: &BTreeMap<String, String>,
+    edition: &str,
+    mut on_checked: impl FnMut(&str, bool),
+) -> io::Result<Vec<Failure>> {
+    let dir = TempDir::new()?;
+    let mut failures = Vec::new();
+
+    for (i, (name, content)) in snippets.iter().enumerate() {
+        // Statements are checked in a function.
+        let src = if is_statements(content) {
+            wrap_statements(content)
+        } else {
+            content.clone()
+        };
+        // Snippet names may contain characters which can't be used in a file name.
+        match check_src(&src, &format!("snippet{}", i), edition, &dir.0)? {
+            Ok(()) => on_checked(name, true),
+            Err(stderr) => {
+                on_checked(name, false);
+                failures.push(Failure {
+                    name: name.clone(),
+                    stderr,
+                });
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_snippets;
+    use crate::parser::{parse_snippet, ParseOptions};
+    use crate::snippet::process_snippets;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_check_snippets() {
+        let src = r#"
+            #[snippet]
+            fn gcd(a: u64, b: u64) -> u64 {
+                if b == 0 { a } else { gcd(b, a % b) }
+            }
+
+            // Forgot `include = "gcd"`
+            #[snippet]
+            fn lcm(a: u64, b: u64) -> u64 {
+                a / gcd(a, b) * b
+            }
+
+            #[snippet(prefix = "use std::collections::HashMap;")]
+            fn counter() -> HashMap<u64, usize> {
+                HashMap::new()
+            }
+
+            fn main() {
+                snippet_block! { name = "zeros", {
+                    let n: usize = "3".parse().unwrap();
+                    let v = vec![0u64; n];
+                }}
+            }
+        "#;
+
+        let snippets = process_snippets(
+            &parse_snippet(src, None, &ParseOptions::default()).0,
+            &BTreeMap::new(),
+        );
+        let mut checked = Vec::new();
+        let failures = check_snippets(&snippets, "2018", |name, ok| {
+            checked.push((name.to_string(), ok))
+        })
+        .unwrap();
+
+        assert_eq!(
+            checked,
+            vec![
+                ("counter".to_string(), true),
+                ("gcd".to_string(), true),
+                ("lcm".to_string(), false),
+                ("zeros".to_string(), true)
+            ]
+        );
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name, "lcm");
+        assert!(failures[0].stderr.contains("gcd"));
+    }
+
+    #[test]
+    fn test_check_snippets_names() {
+        let mut snippets = BTreeMap::new();
+        // Names which can't be file names
+        snippets.insert("io/read line".to_string(), "pub fn f() {}\n".to_string());
+        snippets.insert("..".to_string(), "fn g() -> u64 { \"\" }\n".to_string());
+        let mut checked = Vec::new();
+        let failures = check_snippets(&snippets, "2018", |name, ok| {
+            checked.push((name.to_string(), ok))
+        })
+        .unwrap();
+
+        assert_eq!(
+            checked,
+            vec![
+                ("..".to_string(), false),
+                ("io/read line".to_string(), true)
+            ]
+        );
+        assert_eq!(failures[0].name, "..");
+        assert!(failures[0].stderr.contains("mismatched types"));
+
+        let failures = check_snippets(&BTreeMap::new(), "2018", |_, _| unreachable!()).unwrap();
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn test_check_snippets_edition() {
+        let mut snippets = BTreeMap::new();
+        // `TryFrom` is in the prelude since 2021.
+        snippets.insert(
+            "narrow".to_string(),
+            "pub fn narrow(x: u32) -> Option<u8> { u8::try_from(x).ok() }\n".to_string(),
+        );
+        let failed = |edition| check_snippets(&snippets, edition, |_, _| ()).unwrap().len();
+        assert_eq!(failed("2021"), 0);
+        assert_eq!(failed("2018"), 1);
+    }
+
+    #[test]
+    fn test_check_wrap_mod() {
+        let src = r#"
+            mod math {
+                #[snippet]
+                pub fn gcd(a: u64, b: u64) -> u64 {
+                    if b == 0 { a } else { gcd(b, a % b) }
+                }
+            }
+
+            mod modint {
+                use super::math::gcd;
+                use std::collections::HashMap;
+
+                #[snippet(include = "gcd")]
+                pub fn inv(a: u64, m: u64) -> u64 {
+                    assert_eq!(gcd(a, m), 1);
+                    (1..m).find(|x| a * x % m == 1).unwrap()
+                }
+            }
+
+            mod graph {
+                use super::modint::{inv, Unused};
+                use crate::other::Item;
+
+                #[snippet(wrap_mod, include = "inv")]
+                pub fn f() -> u64 {
+                    inv(3, 7) + super::math::gcd(4, 6)
+                }
+            }
+        "#;
+
+        let snippets = process_snippets(
+            &parse_snippet(src, None, &ParseOptions::default()).0,
+            &BTreeMap::new(),
+        );
+        // Dependencies are wrapped with the `use` declarations which refer to emitted items.
+        let f = &snippets["f"];
+        assert!(f.contains("pub mod math"));
+        assert!(f.contains("use super::math::gcd;"));
+        assert!(f.contains("use super::modint::inv;"));
+        assert!(f.contains("use std::collections::HashMap;"));
+        assert!(!f.contains("Unused"));
+        assert!(!f.contains("other"));
+
+        let failures = check_snippets(&snippets, "2018", |_, _| ()).unwrap();
+        assert!(failures.is_empty(), "{:?}", failures);
+    }
+}
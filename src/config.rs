@@ -1,105 +1,294 @@
 use clap::ArgMatches;
 use std::collections::BTreeMap;
 use std::fs;
-use std::iter;
+use std::io;
 use std::path::{Path, PathBuf};
 
+use crate::cfg::Cfg;
+use crate::export;
 use crate::fsutil;
+use crate::modtree::{self, ModuleTree};
+use crate::subst;
 use crate::writer;
 use glob::glob;
 
 #[derive(Debug)]
 pub struct Config<'a> {
+    pub command: Command<'a>,
     pub target: Target<'a>,
-    pub output_type: OutputType,
+    pub format: Format,
+    // Where directory-based formats (e.g. yasnippet) are written. Current directory if `None`
+    pub out_dir: Option<&'a str>,
+    // Cut snippets out of the source text and don't format them.
+    pub verbatim: bool,
+    // Add dependencies inferred from identifier usage.
+    pub infer_deps: bool,
+    // Emit snippets inside modules mirroring where they are defined.
+    pub wrap_mod: bool,
+    // Substitution of generic parameters and consts for all snippets. e.g. `[("MOD", "998244353")]`
+    pub subst: Vec<(String, String)>,
+    // Options to evaluate `cfg`. `None` unless `--features` or `--cfg` is given.
+    pub cfg: Option<Cfg>,
+}
+
+#[derive(Debug)]
+pub enum Command<'a> {
+    // Write extracted snippets. Default
+    Extract,
+    // Compile each snippet standalone
+    Check {
+        // e.g. `2021`
+        edition: String,
+    },
+    // Report problems of snippets without writing them
+    Lint,
+    // Expand snippets into a solution file
+    Bundle {
+        main: &'a str,
+        crate_names: Vec<String>,
+        // Remove items unreachable from the solution
+        prune: bool,
+    },
 }
 
 #[derive(Debug)]
 pub enum Target<'a> {
-    // <project_root>/src. Default
+    // Modules reachable from the crate roots of the project. Default
     ProjectSrc,
     // Args
     Paths(Vec<&'a str>),
 }
 
+#[derive(Debug)]
+pub enum Format {
+    // Snippets for an editor
+    Editor(OutputType),
+    // All snippets with metadata. See `export::Index`
+    Index(IndexFormat),
+}
+
 #[derive(Debug)]
 pub enum OutputType {
     Neosnippet,
     VScode,
     Ultisnips,
+    // One file per snippet in `rust-mode/`
+    Yasnippet,
+    // A Lua module for LuaSnip
+    Luasnip,
+    Vsnip,
+    // Live templates for RustRover and IntelliJ Rust
+    JetBrains,
+    // One `.sublime-snippet` file per snippet
+    Sublime,
+    Zed,
+}
+
+#[derive(Debug)]
+pub enum IndexFormat {
+    Json,
+    // `Json` with one snippet per line
+    Ndjson,
 }
 
 impl<'a> Config<'a> {
     pub fn from_matches(matches: &'a ArgMatches) -> Self {
         Config {
+            command: Command::from_matches(matches),
             target: Target::from_matches(matches),
-            output_type: OutputType::from_matches(matches),
+            format: Format::from_matches(matches),
+            out_dir: matches
+                .subcommand_matches("snippet")
+                .and_then(|m| m.value_of("out_dir")),
+            verbatim: is_present(matches, "verbatim"),
+            infer_deps: is_present(matches, "infer_deps"),
+            wrap_mod: is_present(matches, "wrap_mod"),
+            subst: values_of(matches, "subst")
+                .into_iter()
+                .flat_map(subst::parse_subst)
+                .collect(),
+            cfg: {
+                let features = values_of(matches, "features");
+                let cfgs = values_of(matches, "cfg");
+                if features.is_empty() && cfgs.is_empty() {
+                    None
+                } else {
+                    Some(Cfg::new(features, cfgs))
+                }
+            },
         }
     }
 }
 
+// Values of an option for `cargo snippet` and its subcommand.
+fn values_of<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
+    matches
+        .subcommand_matches("snippet")
+        .map(|m| {
+            m.values_of(name)
+                .into_iter()
+                .flatten()
+                .chain(
+                    m.subcommand()
+                        .1
+                        .and_then(|sub| sub.values_of(name))
+                        .into_iter()
+                        .flatten(),
+                )
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Whether a flag is specified for `cargo snippet` or its subcommand.
+fn is_present(matches: &ArgMatches, name: &str) -> bool {
+    matches
+        .subcommand_matches("snippet")
+        .map(|m| {
+            m.is_present(name)
+                || m.subcommand()
+                    .1
+                    .map(|sub| sub.is_present(name))
+                    .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+impl<'a> Command<'a> {
+    fn from_matches(matches: &'a ArgMatches) -> Self {
+        let snippet = matches.subcommand_matches("snippet");
+        if let Some(check) = snippet.and_then(|m| m.subcommand_matches("check")) {
+            // `--edition`, the edition of the project or rustc's default
+            let edition = check
+                .value_of("edition")
+                .map(|edition| edition.to_string())
+                .or_else(|| {
+                    fsutil::project_root_path().and_then(|root| fsutil::package_edition(&root))
+                })
+                .unwrap_or_else(|| "2015".to_string());
+            return Command::Check { edition };
+        }
+        if snippet.and_then(|m| m.subcommand_matches("lint")).is_some() {
+            return Command::Lint;
+        }
+        snippet
+            .and_then(|m| m.subcommand_matches("bundle"))
+            .and_then(|m| {
+                let crate_names = m
+                    .values_of("crate")
+                    .map(|names| names.map(|s| s.to_string()).collect())
+                    .or_else(|| {
+                        fsutil::project_root_path()
+                            .and_then(|root| fsutil::package_name(&root))
+                            .map(|name| vec![name])
+                    })
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|name: String| name.replace('-', "_"))
+                    .collect();
+                m.value_of("MAIN").map(|main| Command::Bundle {
+                    main,
+                    crate_names,
+                    prune: m.is_present("prune"),
+                })
+            })
+            .unwrap_or(Command::Extract)
+    }
+}
+
 impl<'a> Target<'a> {
     fn from_matches(matches: &'a ArgMatches) -> Self {
         matches
             .subcommand_matches("snippet")
-            .and_then(|m| {
-                m.values_of("PATH")
-                    .map(|path| Target::Paths(path.collect()))
+            .and_then(|m| match m.subcommand() {
+                ("bundle", Some(bundle)) => bundle
+                    .values_of("lib")
+                    .map(|path| Target::Paths(path.collect())),
+                ("check", Some(sub)) | ("lint", Some(sub)) => sub
+                    .values_of("PATH")
+                    .map(|path| Target::Paths(path.collect())),
+                _ => m
+                    .values_of("PATH")
+                    .map(|path| Target::Paths(path.collect())),
             })
             .unwrap_or(Target::ProjectSrc)
     }
 
-    pub fn iter_paths(&self) -> Box<dyn Iterator<Item = PathBuf> + 'a> {
+    pub fn module_tree(&self) -> ModuleTree {
         match self {
             Target::ProjectSrc => fsutil::project_root_path()
-                .and_then(|mut path| {
-                    path.push("src");
-                    path.push("**");
-                    path.push("*.rs");
-                    glob(&format!("{}", path.display())).ok().map(|paths| {
-                        Box::new(paths.filter_map(|e| e.ok())) as Box<dyn Iterator<Item = PathBuf>>
-                    })
+                .map(|root| {
+                    let crate_roots = fsutil::crate_roots(&root);
+                    if crate_roots.is_empty() {
+                        return ModuleTree::from_files(glob_files(root.join("src")));
+                    }
+                    let mut tree = ModuleTree::default();
+                    for crate_root in &crate_roots {
+                        tree.merge(modtree::walk(crate_root));
+                    }
+                    tree
                 })
-                .unwrap_or_else(|| Box::new(iter::empty())),
-            Target::Paths(ref v) => Box::new(
-                v.clone()
-                    .into_iter()
-                    .filter_map(|s| {
-                        fs::metadata(Path::new(s)).ok().and_then(|meta| {
-                            let path = if meta.is_dir() {
-                                let mut path = Path::new(s).to_path_buf();
-                                path.push("**");
-                                path.push("*.rs");
-                                path
-                            } else {
-                                Path::new(s).to_path_buf()
-                            };
-                            glob(&format!("{}", path.display()))
-                                .ok()
-                                .map(|paths| paths.filter_map(|e| e.ok()))
-                        })
-                    })
-                    .flat_map(|i| i),
-            ),
+                .unwrap_or_default(),
+            Target::Paths(ref v) => ModuleTree::from_files(v.iter().flat_map(|s| {
+                let path = Path::new(s).to_path_buf();
+                match fs::metadata(&path) {
+                    Ok(ref meta) if meta.is_dir() => glob_files(path),
+                    Ok(_) => vec![path],
+                    Err(_) => Vec::new(),
+                }
+            })),
         }
     }
 }
 
-impl OutputType {
+// All .rs files in `dir` and its children.
+fn glob_files(mut dir: PathBuf) -> Vec<PathBuf> {
+    dir.push("**");
+    dir.push("*.rs");
+    match glob(&format!("{}", dir.display())) {
+        Ok(paths) => paths.filter_map(|e| e.ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+impl Format {
     fn from_matches(matches: &ArgMatches) -> Self {
         matches
             .subcommand_matches("snippet")
             .and_then(|m| {
                 m.value_of("output_type").map(|t| match t {
-                    "vscode" => OutputType::VScode,
-                    "ultisnips" => OutputType::Ultisnips,
-                    _ => OutputType::Neosnippet,
+                    "vscode" => Format::Editor(OutputType::VScode),
+                    "ultisnips" => Format::Editor(OutputType::Ultisnips),
+                    "yasnippet" => Format::Editor(OutputType::Yasnippet),
+                    "luasnip" => Format::Editor(OutputType::Luasnip),
+                    "vsnip" => Format::Editor(OutputType::Vsnip),
+                    "jetbrains" => Format::Editor(OutputType::JetBrains),
+                    "sublime" => Format::Editor(OutputType::Sublime),
+                    "zed" => Format::Editor(OutputType::Zed),
+                    "json" => Format::Index(IndexFormat::Json),
+                    "ndjson" => Format::Index(IndexFormat::Ndjson),
+                    _ => Format::Editor(OutputType::Neosnippet),
                 })
             })
-            .unwrap_or(OutputType::Neosnippet)
+            .unwrap_or(Format::Editor(OutputType::Neosnippet))
     }
+}
 
-    pub fn write(&self, snippets: &BTreeMap<String, String>) {
+impl IndexFormat {
+    pub fn write(&self, index: &export::Index, out: impl io::Write) -> io::Result<()> {
+        match self {
+            IndexFormat::Json => index.write_json(out),
+            IndexFormat::Ndjson => index.write_ndjson(out),
+        }
+    }
+}
+
+impl OutputType {
+    pub fn write(
+        &self,
+        snippets: &BTreeMap<String, String>,
+        out_dir: Option<&str>,
+    ) -> io::Result<()> {
         match self {
             OutputType::Neosnippet => {
                 writer::write_neosnippet(snippets);
@@ -110,6 +299,25 @@ impl OutputType {
             OutputType::Ultisnips => {
                 writer::write_ultisnips(snippets);
             }
+            OutputType::Yasnippet => {
+                writer::write_yasnippet(snippets, Path::new(out_dir.unwrap_or(".")))?;
+            }
+            OutputType::Luasnip => {
+                writer::write_luasnip(snippets);
+            }
+            OutputType::Vsnip => {
+                writer::write_vsnip(snippets);
+            }
+            OutputType::JetBrains => {
+                writer::write_jetbrains(snippets);
+            }
+            OutputType::Sublime => {
+                writer::write_sublime(snippets, Path::new(out_dir.unwrap_or(".")))?;
+            }
+            OutputType::Zed => {
+                writer::write_zed(snippets);
+            }
         }
+        Ok(())
     }
 }
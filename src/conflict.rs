@@ -0,0 +1,333 @@
+use syn::{Fields, Item};
+
+use crate::diagnostics::{Diagnostic, Location};
+use crate::snippet::{Snippet, SnippetGraph};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Namespace {
+    Type,
+    Value,
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Namespace::Type => write!(f, "type"),
+            Namespace::Value => write!(f, "value"),
+        }
+    }
+}
+
+// Names defined by a top level item with their namespaces.
+// `macro_rules!` is not included because redefining a macro is allowed.
+fn definitions(item: &Item) -> Vec<(Namespace, String)> {
+    let (ident, namespaces): (_, &[Namespace]) = match item {
+        Item::Const(ref x) => (&x.ident, &[Namespace::Value]),
+        Item::Enum(ref x) => (&x.ident, &[Namespace::Type]),
+        Item::Fn(ref x) => (&x.sig.ident, &[Namespace::Value]),
+        Item::Mod(ref x) => (&x.ident, &[Namespace::Type]),
+        Item::Static(ref x) => (&x.ident, &[Namespace::Value]),
+        // Tuple and unit structs also define constructors.
+        Item::Struct(ref x) => match x.fields {
+            Fields::Named(_) => (&x.ident, &[Namespace::Type]),
+            _ => (&x.ident, &[Namespace::Type, Namespace::Value]),
+        },
+        Item::Trait(ref x) => (&x.ident, &[Namespace::Type]),
+        Item::TraitAlias(ref x) => (&x.ident, &[Namespace::Type]),
+        Item::Type(ref x) => (&x.ident, &[Namespace::Type]),
+        Item::Union(ref x) => (&x.ident, &[Namespace::Type]),
+        _ => return Vec::new(),
+    };
+    namespaces
+        .iter()
+        .map(|&ns| (ns, ident.to_string()))
+        .collect()
+}
+
+// Two snippets define the same name when they are combined.
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    // The combined snippet
+    pub snippet: String,
+    pub namespace: Namespace,
+    pub name: String,
+    // Names of the snippets which define `name`. They are the same if one item has multiple names.
+    pub first: String,
+    pub second: String,
+    // Where the second definition comes from
+    pub location: Option<Location>,
+}
+
+// Find names defined twice in the same namespace of the same module in each combined snippet.
+// Each pair of colliding snippets is reported once.
+pub fn find_conflicts(
+    snips: &[Snippet],
+    extra_deps: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<Conflict> {
+    // Definitions of each item. Unparsable content is skipped as `check` reports it.
+    let definitions = snips
+        .iter()
+        .map(|snip| {
+            syn::parse_file(&snip.content)
+                .map(|file| file.items.iter().flat_map(definitions).collect::<Vec<_>>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let graph = SnippetGraph::new(snips, extra_deps);
+    let mut reported = BTreeSet::new();
+    let mut res = Vec::new();
+    for name in graph.names() {
+        let resolution = graph.resolve(Some(name.as_str()));
+        // Items are in their modules if one of the snippets has `wrap_mod`. See `SnippetGraph::concat`.
+        let wrap = snips.iter().any(|snip| {
+            snip.attrs.wrap_mod
+                && resolution
+                    .order
+                    .iter()
+                    .any(|n| snip.attrs.names.contains(n))
+        });
+        // (module path, namespace, name) -> snippet name
+        let mut defined: BTreeMap<(&[String], Namespace, &String), &String> = BTreeMap::new();
+        // Same order as `SnippetGraph::concat`
+        for part in &resolution.order {
+            for (snip, defs) in snips.iter().zip(definitions.iter()) {
+                if !snip.attrs.names.contains(part) {
+                    continue;
+                }
+                let module_path: &[String] = if wrap && snip.wrapped.is_some() {
+                    &snip.module_path
+                } else {
+                    &[]
+                };
+                for (ns, item_name) in defs {
+                    match defined.get(&(module_path, *ns, item_name)) {
+                        Some(&first) => {
+                            let pair = (first.clone(), part.clone(), *ns, item_name.clone());
+                            if reported.insert(pair) {
+                                res.push(Conflict {
+                                    snippet: name.clone(),
+                                    namespace: *ns,
+                                    name: item_name.clone(),
+                                    first: first.clone(),
+                                    second: part.clone(),
+                                    location: snip.location.clone(),
+                                });
+                            }
+                        }
+                        None => {
+                            defined.insert((module_path, *ns, item_name), part);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+pub fn diagnose(
+    snips: &[Snippet],
+    extra_deps: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<Diagnostic> {
+    find_conflicts(snips, extra_deps)
+        .into_iter()
+        .map(|c| {
+            let by = if c.first == c.second {
+                format!("twice in snippet `{}`", c.first)
+            } else {
+                format!("by both snippet `{}` and `{}`", c.first, c.second)
+            };
+            Diagnostic::warning(format!(
+                "{} `{}` is defined {}, which conflict in snippet `{}`",
+                c.namespace, c.name, by, c.snippet
+            ))
+            .at(c.location)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{definitions, diagnose, find_conflicts, Namespace};
+    use crate::parser::{parse_snippet, ParseOptions};
+    use std::collections::BTreeMap;
+
+    // (combined snippet, name, first, second) for each conflict
+    fn conflicts(src: &str) -> Vec<(String, String, String, String)> {
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        find_conflicts(&snips, &BTreeMap::new())
+            .into_iter()
+            .map(|c| (c.snippet, c.name, c.first, c.second))
+            .collect()
+    }
+
+    fn conflict(
+        snippet: &str,
+        name: &str,
+        first: &str,
+        second: &str,
+    ) -> (String, String, String, String) {
+        (
+            snippet.to_string(),
+            name.to_string(),
+            first.to_string(),
+            second.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_definitions() {
+        let file = syn::parse_file(
+            r#"
+            struct Named { x: u64 }
+            struct Tuple(u64);
+            struct Unit;
+            enum E {}
+            fn f() {}
+            macro_rules! m { () => {} }
+            impl Named {}
+            use std::io;
+            "#,
+        )
+        .unwrap();
+        let defs = file.items.iter().flat_map(definitions).collect::<Vec<_>>();
+        assert_eq!(
+            defs,
+            vec![
+                (Namespace::Type, "Named".to_string()),
+                (Namespace::Type, "Tuple".to_string()),
+                (Namespace::Value, "Tuple".to_string()),
+                (Namespace::Type, "Unit".to_string()),
+                (Namespace::Value, "Unit".to_string()),
+                (Namespace::Type, "E".to_string()),
+                (Namespace::Value, "f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_conflicts_namespaces() {
+        let src = r#"
+#[snippet]
+fn pow() {}
+
+#[snippet]
+struct pow {}
+
+#[snippet]
+fn Pair() {}
+
+#[snippet]
+struct Pair(u64, u64);
+
+#[snippet(include = "pow, Pair")]
+fn solve() {}
+"#;
+        // A struct with named fields doesn't define a value.
+        assert_eq!(
+            conflicts(src),
+            vec![conflict("Pair", "Pair", "Pair", "Pair")]
+        );
+    }
+
+    #[test]
+    fn test_find_conflicts_reported_once() {
+        let src = r#"
+#[snippet]
+fn gcd() {}
+
+#[snippet(name = "math")]
+fn gcd() {}
+
+#[snippet(include = "gcd, math")]
+fn lcm() {}
+
+#[snippet(include = "lcm")]
+fn solve() {}
+"#;
+        // `solve` has the same conflict through `lcm`.
+        assert_eq!(conflicts(src), vec![conflict("lcm", "gcd", "gcd", "math")]);
+    }
+
+    #[test]
+    fn test_find_conflicts_unparsable() {
+        let src = r#"
+#[snippet]
+fn x() {}
+
+fn main() {
+    snippet_block! { name = "read", include = "x", {
+        let x = 1;
+    }}
+}
+"#;
+        assert!(conflicts(src).is_empty());
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let src = r#"
+#[snippet("a")]
+const N: usize = 1;
+
+#[snippet("a")]
+const N: usize = 2;
+
+#[snippet("b")]
+const N: usize = 3;
+
+#[snippet(include = "b")]
+fn c() {}
+
+#[snippet(include = "a, c")]
+fn d() {}
+"#;
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        let diags = diagnose(&snips, &BTreeMap::new());
+        assert_eq!(
+            diags.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(),
+            vec![
+                "value `N` is defined twice in snippet `a`, which conflict in snippet `a`",
+                "value `N` is defined by both snippet `a` and `b`, which conflict in snippet `d`",
+            ]
+        );
+        assert_eq!(diags[1].location.as_ref().map(|l| l.line), Some(8));
+    }
+
+    #[test]
+    fn test_find_conflicts_wrap_mod() {
+        let src = r#"
+mod a {
+    #[snippet("a")]
+    pub fn f() {}
+}
+
+mod b {
+    #[snippet("b")]
+    pub fn f() {}
+}
+
+#[snippet(include = "a, b")]
+fn flat() {}
+
+mod c {
+    #[snippet(wrap_mod, include = "a, b")]
+    pub fn wrapped() {}
+}
+"#;
+
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        // Dependencies of a wrapped snippet are in their own modules.
+        let conflicts = find_conflicts(&snips, &BTreeMap::new())
+            .into_iter()
+            .map(|c| (c.snippet, c.first, c.second))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            conflicts,
+            vec![("flat".to_string(), "a".to_string(), "b".to_string())]
+        );
+    }
+}
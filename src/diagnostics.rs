@@ -0,0 +1,214 @@
+use proc_macro2::Span;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::source::Source;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+// A position in a source file with the text of its line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    // 1-indexed
+    pub line: usize,
+    // 0-indexed, counted in chars
+    pub column: usize,
+    // End column of the highlighted part. It's the end of the line if the span has multiple lines.
+    pub end_column: usize,
+    pub line_text: String,
+}
+
+impl Location {
+    pub fn from_span(source: &Source, span: Span, file: Option<&Path>) -> Self {
+        let start = span.start();
+        let end = span.end();
+        let line_text = source.line(start.line).to_string();
+        let end_column = if end.line == start.line {
+            end.column
+        } else {
+            line_text.chars().count()
+        };
+        Location {
+            file: file.map(|f| f.to_path_buf()),
+            line: start.line,
+            column: start.column,
+            end_column,
+            line_text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            level: Level::Error,
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            level: Level::Warning,
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    pub fn at(mut self, location: Option<Location>) -> Self {
+        self.location = location;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+}
+
+// rustc style. e.g.
+//
+// error: unknown snippet attribute `foo`
+//  --> src/lib.rs:3:11
+//   |
+// 3 | #[snippet(foo = "bar")]
+//   |           ^^^^^^^^^^^
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        write!(f, "{}: {}", level, self.message)?;
+
+        if let Some(ref loc) = self.location {
+            let line_no = loc.line.to_string();
+            let gutter = " ".repeat(line_no.len());
+            let file = loc
+                .file
+                .as_ref()
+                .map(|f| f.display().to_string())
+                .unwrap_or_else(|| "<unknown>".to_string());
+            let marker_len = loc.end_column.saturating_sub(loc.column).max(1);
+
+            writeln!(f)?;
+            writeln!(f, "{}--> {}:{}:{}", gutter, file, loc.line, loc.column + 1)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", line_no, loc.line_text)?;
+            write!(
+                f,
+                "{} | {}{}",
+                gutter,
+                " ".repeat(loc.column),
+                "^".repeat(marker_len)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Print diagnostics to stderr. Returns whether there is an error.
+pub fn emit(diagnostics: &[Diagnostic]) -> bool {
+    for diag in diagnostics {
+        eprintln!("{}\n", diag);
+    }
+    diagnostics.iter().any(|d| d.is_error())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{emit, Diagnostic, Location};
+    use crate::source::Source;
+    use std::path::Path;
+
+    // Location of the `index`th item in `src`
+    fn item_location(src: &str, index: usize) -> Location {
+        let file = syn::parse_file(src).unwrap();
+        let span = syn::spanned::Spanned::span(&file.items[index]);
+        Location::from_span(&Source::new(src), span, None)
+    }
+
+    #[test]
+    fn test_display() {
+        let src = "fn main() {}\n#[snippet(foo = \"bar\")]\nfn foo() {}\n";
+        let file = syn::parse_file(src).unwrap();
+        let attr = match file.items[1] {
+            syn::Item::Fn(ref f) => f.attrs[0].clone(),
+            _ => unreachable!(),
+        };
+        let meta = match attr.parse_meta().unwrap() {
+            syn::Meta::List(list) => list.nested[0].clone(),
+            _ => unreachable!(),
+        };
+
+        let source = Source::new(src);
+        let diag =
+            Diagnostic::error("unknown snippet attribute `foo`").at(Some(Location::from_span(
+                &source,
+                syn::spanned::Spanned::span(&meta),
+                Some(Path::new("src/lib.rs")),
+            )));
+        assert_eq!(
+            diag.to_string(),
+            r#"error: unknown snippet attribute `foo`
+ --> src/lib.rs:2:11
+  |
+2 | #[snippet(foo = "bar")]
+  |           ^^^^^^^^^^^"#
+        );
+
+        assert_eq!(
+            Diagnostic::warning("dependency cycle: a -> b -> a").to_string(),
+            "warning: dependency cycle: a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn test_location_multiline() {
+        let loc = item_location("fn a() {\n}\n", 0);
+        assert_eq!((loc.line, loc.column, loc.end_column), (1, 0, 8));
+        assert_eq!(loc.line_text, "fn a() {");
+        assert!(loc.file.is_none());
+    }
+
+    #[test]
+    fn test_location_chars() {
+        // Columns are in chars, not bytes.
+        let loc = item_location("const S: &str = \"é\"; const T: u8 = 0;\n", 1);
+        assert_eq!((loc.line, loc.column, loc.end_column), (1, 21, 37));
+    }
+
+    #[test]
+    fn test_display_gutter() {
+        let diag = Diagnostic::warning("empty").at(Some(Location {
+            file: None,
+            line: 10,
+            column: 4,
+            end_column: 4,
+            line_text: "    }".to_string(),
+        }));
+        // The gutter is as wide as the line number and an empty span has one marker.
+        assert_eq!(
+            diag.to_string(),
+            "warning: empty\n  --> <unknown>:10:5\n   |\n10 |     }\n   |     ^"
+        );
+    }
+
+    #[test]
+    fn test_emit() {
+        assert!(!emit(&[]));
+        assert!(!emit(&[Diagnostic::warning("a")]));
+        assert!(emit(&[Diagnostic::warning("a"), Diagnostic::error("b")]));
+    }
+}
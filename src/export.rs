@@ -0,0 +1,241 @@
+use serde_derive::Serialize;
+
+use crate::snippet::{Snippet, SnippetGraph};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+// Version of the output schema. It's bumped on incompatible changes. Adding fields is compatible.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// An item marked as a snippet. Items which share a name make up one snippet.
+#[derive(Serialize, Debug)]
+pub struct Source {
+    // Sorted
+    pub names: Vec<String>,
+    // Dependencies declared by `include` of this item
+    pub uses: Vec<String>,
+    pub prefix: String,
+    pub doc_hidden: bool,
+    pub file: Option<String>,
+    // 1-indexed and inclusive
+    pub lines: Option<(usize, usize)>,
+    // e.g. `["math"]` for `crate::math`
+    pub module_path: Vec<String>,
+    pub content: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Entry {
+    pub name: String,
+    // Dependencies declared by `include`
+    pub uses: Vec<String>,
+    // Dependencies found by `--infer-deps` and not declared
+    pub inferred_uses: Vec<String>,
+    // Snippets concatenated into `content`. Dependencies come first and the last is this snippet.
+    pub order: Vec<String>,
+    pub sources: Vec<Source>,
+    // What `cargo snippet` emits before rustfmt. Placeholders are left as `__snip_<number>_<name>`.
+    pub content: String,
+    // `None` with `--verbatim`, for `verbatim` snippets or if rustfmt fails
+    pub formatted: Option<String>,
+}
+
+// All snippets with their metadata for tools built on top of snippets.
+#[derive(Serialize, Debug)]
+pub struct Index {
+    pub version: u32,
+    pub snippets: Vec<Entry>,
+}
+
+// A line of NDJSON. Each line has the version since lines may be read separately.
+#[derive(Serialize)]
+struct Record<'a> {
+    version: u32,
+    #[serde(flatten)]
+    entry: &'a Entry,
+}
+
+impl Index {
+    // `formatted` is the formatted content of each snippet.
+    pub fn new(
+        snips: &[Snippet],
+        inferred: &BTreeMap<String, BTreeSet<String>>,
+        formatted: &BTreeMap<String, String>,
+    ) -> Self {
+        let graph = SnippetGraph::new(snips, inferred);
+        let snippets = graph
+            .names()
+            .map(|name| {
+                let sources = snips
+                    .iter()
+                    .filter(|snip| snip.attrs.names.contains(name))
+                    .collect::<Vec<_>>();
+                let mut uses = Vec::new();
+                for dep in sources.iter().flat_map(|snip| snip.attrs.uses.iter()) {
+                    if !uses.contains(dep) {
+                        uses.push(dep.clone());
+                    }
+                }
+                let inferred_uses = inferred
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .filter(|dep| !uses.contains(dep))
+                    .cloned()
+                    .collect();
+                let order = graph.resolve(Some(name.as_str())).order;
+                Entry {
+                    name: name.clone(),
+                    uses,
+                    inferred_uses,
+                    content: graph.concat(&order),
+                    order,
+                    sources: sources.into_iter().map(source).collect(),
+                    formatted: formatted.get(name).cloned(),
+                }
+            })
+            .collect();
+
+        Index {
+            version: SCHEMA_VERSION,
+            snippets,
+        }
+    }
+
+    pub fn write_json(&self, mut out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)
+    }
+
+    pub fn write_ndjson(&self, mut out: impl Write) -> io::Result<()> {
+        for entry in &self.snippets {
+            let record = Record {
+                version: self.version,
+                entry,
+            };
+            serde_json::to_writer(&mut out, &record)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn source(snip: &Snippet) -> Source {
+    let mut names = snip.attrs.names.iter().cloned().collect::<Vec<_>>();
+    names.sort();
+    Source {
+        names,
+        uses: snip.attrs.uses.clone(),
+        prefix: snip.attrs.prefix.clone(),
+        doc_hidden: snip.attrs.doc_hidden,
+        file: snip
+            .location
+            .as_ref()
+            .and_then(|l| l.file.as_ref())
+            .map(|f| f.to_string_lossy().into_owned()),
+        lines: snip.lines,
+        module_path: snip.module_path.clone(),
+        content: snip.content.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Index, SCHEMA_VERSION};
+    use crate::parser::{parse_snippet, ParseOptions};
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn index(src: &str, inferred: &[(&str, &[&str])]) -> Index {
+        let (snips, _) = parse_snippet(src, None, &ParseOptions::default());
+        let inferred = inferred
+            .iter()
+            .map(|(name, deps)| {
+                let deps = deps.iter().map(|d| d.to_string()).collect::<BTreeSet<_>>();
+                (name.to_string(), deps)
+            })
+            .collect();
+        Index::new(&snips, &inferred, &BTreeMap::new())
+    }
+
+    #[test]
+    fn test_index_sources() {
+        let src = r#"
+#[snippet("b")]
+#[snippet(name = "a", include = "x")]
+fn f() {}
+
+mod m {
+    #[snippet(name = "a", include = "x, y")]
+    fn g() {}
+}
+
+#[snippet]
+fn x() {}
+
+#[snippet]
+fn y() {}
+"#;
+        let index = index(src, &[("a", &["x", "f"])]);
+        let a = &index.snippets[0];
+        assert_eq!(a.name, "a");
+        // Declared dependencies are merged and not repeated as inferred ones.
+        assert_eq!(a.uses, vec!["x", "y"]);
+        assert_eq!(a.inferred_uses, vec!["f"]);
+        assert_eq!(a.sources.len(), 2);
+        assert_eq!(a.sources[0].names, vec!["a", "b"]);
+        assert_eq!(a.sources[0].uses, vec!["x"]);
+        assert_eq!(a.sources[0].lines, Some((2, 4)));
+        assert!(a.sources[0].module_path.is_empty());
+        assert!(a.sources[0].file.is_none());
+        assert_eq!(a.sources[1].module_path, vec!["m"]);
+        assert_eq!(a.order.last().map(|s| s.as_str()), Some("a"));
+        assert!(a.formatted.is_none());
+    }
+
+    #[test]
+    fn test_index_empty() {
+        let index = index("fn main() {}", &[]);
+        assert!(index.snippets.is_empty());
+
+        let mut out = Vec::new();
+        index.write_ndjson(&mut out).unwrap();
+        assert!(out.is_empty());
+
+        let mut out = Vec::new();
+        index.write_json(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("}\n"));
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "version": SCHEMA_VERSION, "snippets": [] })
+        );
+    }
+
+    #[test]
+    fn test_write_ndjson() {
+        let src = r#"
+#[snippet(doc_hidden)]
+fn a() {}
+
+#[snippet(include = "a")]
+fn b() {}
+"#;
+        let mut out = Vec::new();
+        index(src, &[]).write_ndjson(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let records = out
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        // Each line is self-contained.
+        for record in &records {
+            assert_eq!(record["version"], SCHEMA_VERSION);
+            assert!(record.get("snippets").is_none());
+        }
+        assert_eq!(records[0]["sources"][0]["doc_hidden"], true);
+        assert_eq!(records[1]["order"], serde_json::json!(["a", "b"]));
+        assert_eq!(records[1]["formatted"], serde_json::Value::Null);
+    }
+}
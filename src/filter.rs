@@ -0,0 +1,171 @@
+use quote::ToTokens;
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+use syn::{Attribute, Expr, Item, Macro, Meta, NestedMeta, Stmt};
+
+use crate::cfg::{self, NodeFilter};
+use crate::source::{Edit, Source};
+
+// Removes items marked by `#[snippet_skip]` or `#[snippet(skip)]` from a snippet.
+// Test-only code is also removed unless `keep_tests` is specified.
+// i.e. `#[cfg(test)]` items, `#[test]` and `#[bench]` functions and `debug_assert!` statements.
+pub struct ContentFilter {
+    strip_tests: bool,
+}
+
+impl ContentFilter {
+    pub fn new(strip_tests: bool) -> Self {
+        ContentFilter { strip_tests }
+    }
+
+    pub fn rewrite(&self, f: impl FnOnce(&mut dyn VisitMut)) {
+        cfg::strip_nodes(self, f);
+    }
+
+    pub fn edits<'ast>(&self, source: &Source, f: impl FnOnce(&mut dyn Visit<'ast>)) -> Vec<Edit> {
+        cfg::node_edits(self, source, f)
+    }
+
+    fn is_removed(&self, attrs: &[Attribute]) -> bool {
+        attrs
+            .iter()
+            .any(|attr| is_skip_attr(attr) || self.strip_tests && is_test_attr(attr))
+    }
+}
+
+// `#[snippet_skip]` or `#[snippet(skip)]`
+pub fn is_skip_attr(attr: &Attribute) -> bool {
+    match attr.path.to_token_stream().to_string().as_str() {
+        "snippet_skip" | "cargo_snippet :: snippet_skip" => true,
+        "snippet" | "cargo_snippet :: snippet" => match attr.parse_meta() {
+            Ok(Meta::List(ref list)) => list
+                .nested
+                .iter()
+                .any(|n| matches!(n, NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("skip"))),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// `test` or `all(test, ...)`
+fn is_test_only_cfg(pred: &NestedMeta) -> bool {
+    match pred {
+        NestedMeta::Meta(Meta::Path(ref path)) => path.is_ident("test"),
+        NestedMeta::Meta(Meta::List(ref list)) if list.path.is_ident("all") => {
+            list.nested.iter().any(is_test_only_cfg)
+        }
+        _ => false,
+    }
+}
+
+fn is_test_attr(attr: &Attribute) -> bool {
+    if attr.path.is_ident("test") || attr.path.is_ident("bench") {
+        return true;
+    }
+    match attr.parse_meta() {
+        Ok(Meta::List(ref list)) if list.path.is_ident("cfg") && list.nested.len() == 1 => {
+            is_test_only_cfg(&list.nested[0])
+        }
+        _ => false,
+    }
+}
+
+fn is_debug_assert(mac: &Macro) -> bool {
+    matches!(
+        mac.path
+            .segments
+            .last()
+            .map(|s| s.ident.to_string())
+            .as_deref(),
+        Some("debug_assert") | Some("debug_assert_eq") | Some("debug_assert_ne")
+    )
+}
+
+impl NodeFilter for ContentFilter {
+    fn process(&self, attrs: &mut Vec<Attribute>) -> bool {
+        !self.is_removed(attrs)
+    }
+
+    fn attr_edits(&self, _source: &Source, attrs: &[Attribute]) -> Option<Vec<Edit>> {
+        if self.is_removed(attrs) {
+            None
+        } else {
+            Some(Vec::new())
+        }
+    }
+
+    fn keep_stmt(&self, stmt: &Stmt) -> bool {
+        if !self.strip_tests {
+            return true;
+        }
+        match stmt {
+            Stmt::Item(Item::Macro(ref item)) => !is_debug_assert(&item.mac),
+            Stmt::Expr(Expr::Macro(ref expr)) | Stmt::Semi(Expr::Macro(ref expr), _) => {
+                !is_debug_assert(&expr.mac)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_debug_assert, is_skip_attr, is_test_attr, ContentFilter};
+    use crate::cfg::NodeFilter;
+    use syn::{Attribute, ItemFn, Stmt};
+
+    fn attr(src: &str) -> Attribute {
+        let item: ItemFn = syn::parse_str(&format!("{} fn f() {{}}", src)).unwrap();
+        item.attrs[0].clone()
+    }
+
+    #[test]
+    fn test_is_skip_attr() {
+        assert!(is_skip_attr(&attr("#[snippet_skip]")));
+        assert!(is_skip_attr(&attr("#[cargo_snippet::snippet_skip]")));
+        assert!(is_skip_attr(&attr("#[snippet(name = \"a\", skip)]")));
+        assert!(is_skip_attr(&attr("#[cargo_snippet::snippet(skip)]")));
+        // A snippet named `skip`
+        assert!(!is_skip_attr(&attr("#[snippet(\"skip\")]")));
+        assert!(!is_skip_attr(&attr("#[snippet(skip = true)]")));
+        assert!(!is_skip_attr(&attr("#[skip]")));
+    }
+
+    #[test]
+    fn test_is_test_attr() {
+        assert!(is_test_attr(&attr("#[test]")));
+        assert!(is_test_attr(&attr("#[bench]")));
+        assert!(is_test_attr(&attr("#[cfg(test)]")));
+        assert!(is_test_attr(&attr("#[cfg(all(unix, test))]")));
+        assert!(is_test_attr(&attr("#[cfg(all(unix, all(test)))]")));
+        // Code which may be compiled outside tests
+        assert!(!is_test_attr(&attr("#[cfg(any(unix, test))]")));
+        assert!(!is_test_attr(&attr("#[cfg(not(test))]")));
+        assert!(!is_test_attr(&attr("#[cfg(test, unix)]")));
+        assert!(!is_test_attr(&attr("#[cfg_attr(test, derive(Debug))]")));
+    }
+
+    #[test]
+    fn test_keep_stmt() {
+        let stmts = |src: &str| -> Vec<Stmt> {
+            let item: ItemFn = syn::parse_str(&format!("fn f() {{ {} }}", src)).unwrap();
+            item.block.stmts
+        };
+        // The last one is an expression without a semicolon.
+        let src = "debug_assert!(a); std::debug_assert_eq!(a, b); assert!(a); debug!(a); \
+                   debug_assert_ne!(a, b)";
+        let strip = ContentFilter::new(true);
+        let kept = stmts(src)
+            .iter()
+            .map(|stmt| strip.keep_stmt(stmt))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![false, false, true, true, false]);
+        assert!(stmts(src)
+            .iter()
+            .all(|stmt| ContentFilter::new(false).keep_stmt(stmt)));
+
+        let mac: syn::Macro = syn::parse_str("core::debug_assert!(a)").unwrap();
+        assert!(is_debug_assert(&mac));
+    }
+}
@@ -1,6 +1,9 @@
+use toml::Value;
+
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Find project root directory from current directory
 pub fn project_root_path() -> Option<PathBuf> {
@@ -20,3 +23,251 @@ pub fn project_root_path() -> Option<PathBuf> {
         }
     })
 }
+
+// <dir>/Cargo.toml
+fn read_manifest(dir: &Path) -> Option<Value> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    match manifest.parse() {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            log::warn!(
+                "Failed to parse {}: {}",
+                dir.join("Cargo.toml").display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+// Manifest of the workspace which <project_root> belongs to. It may be <project_root> itself.
+fn workspace_manifest(project_root: &Path) -> Option<Value> {
+    project_root
+        .ancestors()
+        .filter_map(read_manifest)
+        .find(|manifest| manifest.get("workspace").is_some())
+}
+
+// Value of `key` in `[package]` of <project_root>/Cargo.toml.
+// `key.workspace = true` is resolved by `[workspace.package]` of the workspace root.
+fn package_value(project_root: &Path, key: &str) -> Option<String> {
+    let value = read_manifest(project_root)?
+        .get("package")?
+        .get(key)?
+        .clone();
+    let value = match value.get("workspace").and_then(Value::as_bool) {
+        Some(true) => workspace_manifest(project_root)?
+            .get("workspace")?
+            .get("package")?
+            .get(key)?
+            .clone(),
+        _ => value,
+    };
+    value.as_str().map(|s| s.to_string())
+}
+
+// Read package name from <project_root>/Cargo.toml
+pub fn package_name(project_root: &Path) -> Option<String> {
+    package_value(project_root, "name")
+}
+
+// Read edition from <project_root>/Cargo.toml
+pub fn package_edition(project_root: &Path) -> Option<String> {
+    package_value(project_root, "edition")
+}
+
+// `path` of the lib target and bin targets in <project_root>/Cargo.toml
+fn target_paths(project_root: &Path) -> Vec<String> {
+    let manifest = match read_manifest(project_root) {
+        Some(manifest) => manifest,
+        None => return Vec::new(),
+    };
+    let lib = manifest.get("lib").into_iter();
+    let bins = manifest
+        .get("bin")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    lib.chain(bins)
+        .filter_map(|target| target.get("path")?.as_str())
+        .map(|path| path.to_string())
+        .collect()
+}
+
+// Bin targets discovered by cargo. i.e. src/bin/*.rs and src/bin/*/main.rs
+fn auto_bins(project_root: &Path) -> Vec<PathBuf> {
+    let mut bins = match fs::read_dir(project_root.join("src/bin")) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| {
+                if path.is_dir() {
+                    Some(path.join("main.rs"))
+                } else if path.extension() == Some(OsStr::new("rs")) {
+                    Some(path)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    bins.sort();
+    bins
+}
+
+// Crate root files of the lib and bin targets.
+// Paths in Cargo.toml are used if specified, otherwise src/lib.rs, src/main.rs and src/bin/.
+pub fn crate_roots(project_root: &Path) -> Vec<PathBuf> {
+    let mut roots = target_paths(project_root)
+        .into_iter()
+        .map(|path| project_root.join(path))
+        .collect::<Vec<_>>();
+    let defaults = ["src/lib.rs", "src/main.rs"]
+        .iter()
+        .map(|path| project_root.join(path))
+        .chain(auto_bins(project_root));
+    for path in defaults {
+        if !roots.contains(&path) {
+            roots.push(path);
+        }
+    }
+    roots.retain(|path| path.is_file());
+    roots
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crate_roots, package_edition, package_name};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Write `files` in a temporary directory named after `name`
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "cargo-snippet-fsutil-{}-{}",
+            name,
+            std::process::id()
+        ));
+        for (path, content) in files.iter() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_crate_roots() {
+        let dir = env::temp_dir().join(format!("cargo-snippet-fsutil-{}", std::process::id()));
+        let files = [
+            (
+                "Cargo.toml",
+                "[package]\nname = \"a\"\n[[bin]]\nname = \"c\"\npath = \"c.rs\"\n",
+            ),
+            ("c.rs", ""),
+            ("src/lib.rs", ""),
+            ("src/bin/b.rs", ""),
+            ("src/bin/a/main.rs", ""),
+            ("src/bin/a/util.rs", ""),
+            ("src/bin/empty/lib.rs", ""),
+            ("src/bin/notes.txt", ""),
+        ];
+        for (path, content) in files.iter() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let roots = crate_roots(&dir);
+        let expected = vec!["c.rs", "src/lib.rs", "src/bin/a/main.rs", "src/bin/b.rs"]
+            .into_iter()
+            .map(|path| dir.join(path))
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(roots, expected);
+    }
+
+    #[test]
+    fn test_manifest_syntax() {
+        let dir = write_files(
+            "syntax",
+            &[
+                (
+                    "Cargo.toml",
+                    "[package] # the library\n\
+                     name = 'my-lib'\n\
+                     lib = { path = \"ignored.rs\" }\n\
+                     [lib]\n\
+                     path = 'lib/mod.rs'\n\
+                     [features]\n\
+                     name = \"other\"\n",
+                ),
+                ("lib/mod.rs", ""),
+                ("src/main.rs", ""),
+            ],
+        );
+
+        let name = package_name(&dir);
+        let roots = crate_roots(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(name.as_deref(), Some("my-lib"));
+        assert_eq!(roots, vec![dir.join("lib/mod.rs"), dir.join("src/main.rs")]);
+    }
+
+    #[test]
+    fn test_manifest_inline_targets() {
+        let dir = write_files(
+            "inline",
+            &[
+                (
+                    "Cargo.toml",
+                    "package = { name = \"a\" }\n\
+                     lib = { path = \"a.rs\" }\n\
+                     bin = [{ name = \"b\", path = \"b.rs\" }, { name = \"c\" }]\n",
+                ),
+                ("a.rs", ""),
+                ("b.rs", ""),
+            ],
+        );
+
+        let name = package_name(&dir);
+        let roots = crate_roots(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(name.as_deref(), Some("a"));
+        assert_eq!(roots, vec![dir.join("a.rs"), dir.join("b.rs")]);
+    }
+
+    #[test]
+    fn test_manifest_workspace() {
+        let dir = write_files(
+            "workspace",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"lib\"]\n\
+                     [workspace.package]\nname = \"shared\"\nedition = \"2021\"\n",
+                ),
+                (
+                    "lib/Cargo.toml",
+                    "[package]\nname.workspace = true\nedition = { workspace = true }\n",
+                ),
+                ("broken/Cargo.toml", "[package\nname = \"broken\"\n"),
+            ],
+        );
+
+        let inherited = package_name(&dir.join("lib"));
+        let edition = package_edition(&dir.join("lib"));
+        let broken = package_name(&dir.join("broken"));
+        let missing = package_name(&dir.join("missing"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(inherited.as_deref(), Some("shared"));
+        assert_eq!(edition.as_deref(), Some("2021"));
+        assert_eq!(broken, None);
+        assert_eq!(missing, None);
+    }
+}
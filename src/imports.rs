@@ -0,0 +1,348 @@
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::{Item, ItemUse, UseTree};
+
+use crate::source::Source;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Imports under a path, merged like `std::{io::{self, Read}, str::FromStr}`.
+#[derive(Default, Debug)]
+struct UseNode {
+    // `self`, `*` and renames like `Read as _` imported at this path
+    leaves: BTreeSet<String>,
+    children: BTreeMap<String, UseNode>,
+}
+
+impl UseNode {
+    fn insert(&mut self, tree: &UseTree) {
+        match tree {
+            UseTree::Path(ref path) => self
+                .children
+                .entry(path.ident.to_string())
+                .or_default()
+                .insert(&path.tree),
+            UseTree::Name(ref name) if name.ident == "self" => {
+                self.leaves.insert("self".to_string());
+            }
+            // `use a::b;` is the same as `use a::b::{self};`
+            UseTree::Name(ref name) => {
+                self.children
+                    .entry(name.ident.to_string())
+                    .or_default()
+                    .leaves
+                    .insert("self".to_string());
+            }
+            UseTree::Rename(ref rename) => {
+                self.leaves
+                    .insert(format!("{} as {}", rename.ident, rename.rename));
+            }
+            UseTree::Glob(_) => {
+                self.leaves.insert("*".to_string());
+            }
+            UseTree::Group(ref group) => {
+                for tree in &group.items {
+                    self.insert(tree);
+                }
+            }
+        }
+    }
+
+    // Imports relative to this node in alphabetical order. `self` comes first as rustfmt does.
+    fn entries(&self) -> Vec<String> {
+        let mut res = self
+            .leaves
+            .iter()
+            .filter(|l| *l != "self")
+            .cloned()
+            .chain(self.children.iter().map(|(name, child)| child.render(name)))
+            .collect::<Vec<_>>();
+        res.sort();
+        if self.leaves.contains("self") {
+            res.insert(0, "self".to_string());
+        }
+        res
+    }
+
+    // `name` followed by imports under it. e.g. `io::{self, Read}`
+    fn render(&self, name: &str) -> String {
+        let entries = self.entries();
+        match entries.as_slice() {
+            [only] if only == "self" => name.to_string(),
+            [only] => format!("{}::{}", name, only),
+            _ => format!("{}::{{{}}}", name, entries.join(", ")),
+        }
+    }
+}
+
+enum Part<'a> {
+    // Original text of an inner attribute. e.g. `#![allow(dead_code)]`
+    InnerAttr(&'a str),
+    Use(ItemUse),
+    // Original text of other items
+    Other(&'a str),
+}
+
+// Split `prefix` into inner attributes and items. The whole text is one part if it can't be parsed.
+fn split_prefix(prefix: &str) -> Vec<Part<'_>> {
+    let file = match syn::parse_file(prefix) {
+        Ok(file) => file,
+        Err(_) => return vec![Part::Other(prefix)],
+    };
+    let source = Source::new(prefix);
+    let attrs = file
+        .attrs
+        .iter()
+        .map(|attr| Part::InnerAttr(&prefix[source.range(attr.span())]));
+    let items = file.items.into_iter().map(|item| match item {
+        Item::Use(item_use) => Part::Use(item_use),
+        item => Part::Other(&prefix[source.range(item.span())]),
+    });
+    attrs.collect::<Vec<_>>().into_iter().chain(items).collect()
+}
+
+// Attributes, visibility and `use` of a declaration. e.g. `pub use ::`
+fn use_head(item_use: &ItemUse) -> String {
+    let mut head = item_use
+        .attrs
+        .iter()
+        .map(|attr| format!("{}\n", attr.to_token_stream()))
+        .collect::<String>();
+    let vis = item_use.vis.to_token_stream().to_string();
+    if !vis.is_empty() {
+        head += &vis;
+        head.push(' ');
+    }
+    head += "use ";
+    if item_use.leading_colon.is_some() {
+        head += "::";
+    }
+    head
+}
+
+fn collect_leaves(tree: &UseTree, path: &mut Vec<String>, res: &mut Vec<(Vec<String>, String)>) {
+    match tree {
+        UseTree::Path(ref p) => {
+            path.push(p.ident.to_string());
+            collect_leaves(&p.tree, path, res);
+            path.pop();
+        }
+        UseTree::Name(ref name) => res.push((path.clone(), name.ident.to_string())),
+        UseTree::Rename(ref rename) => res.push((
+            path.clone(),
+            format!("{} as {}", rename.ident, rename.rename),
+        )),
+        UseTree::Glob(_) => res.push((path.clone(), "*".to_string())),
+        UseTree::Group(ref group) => {
+            for tree in &group.items {
+                collect_leaves(tree, path, res);
+            }
+        }
+    }
+}
+
+// Each import of a `use` declaration as (path, last part, declaration of it alone).
+// e.g. `(["std", "io"], "Read", "use std::io::Read;")` for `use std::io::{Read, Write};`
+// The last part may be `self`, `*` or a rename like `Read as _`.
+pub fn use_leaves(item_use: &ItemUse) -> Vec<(Vec<String>, String, String)> {
+    let head = use_head(item_use);
+    let mut leaves = Vec::new();
+    collect_leaves(&item_use.tree, &mut Vec::new(), &mut leaves);
+    leaves
+        .into_iter()
+        .map(|(path, last)| {
+            let decl = match (path.is_empty(), last.as_str()) {
+                (true, _) => format!("{}{};", head, last),
+                (false, "self") => format!("{}{}::{{self}};", head, path.join("::")),
+                (false, _) => format!("{}{}::{};", head, path.join("::"), last),
+            };
+            (path, last, decl)
+        })
+        .collect()
+}
+
+// An item of the merged prefix.
+enum Entry<'a> {
+    // Index of an import group
+    Use(usize),
+    Other(&'a str),
+}
+
+// Combine prefixes of snippets.
+// `use` declarations are deduplicated and merged by their paths.
+// Inner attributes must precede items, so they come first.
+// The merged imports are emitted where the first of them is. Other items are kept in order
+// (identical ones are emitted once), so the order written by the author is preserved.
+pub fn merge_prefixes<'a>(prefixes: impl IntoIterator<Item = &'a String>) -> String {
+    let mut inner_attrs: Vec<&str> = Vec::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    // Imports with the same attributes, visibility and leading `::` are merged.
+    let mut groups: Vec<(String, UseNode)> = Vec::new();
+
+    for part in prefixes.into_iter().flat_map(|p| split_prefix(p)) {
+        match part {
+            Part::InnerAttr(text) => {
+                if !inner_attrs.contains(&text) {
+                    inner_attrs.push(text);
+                }
+            }
+            Part::Use(item_use) => {
+                let head = use_head(&item_use);
+                let i = match groups.iter().position(|(h, _)| *h == head) {
+                    Some(i) => i,
+                    None => {
+                        groups.push((head, UseNode::default()));
+                        entries.push(Entry::Use(groups.len() - 1));
+                        groups.len() - 1
+                    }
+                };
+                groups[i].1.insert(&item_use.tree);
+            }
+            Part::Other(text) => {
+                if !entries
+                    .iter()
+                    .any(|e| matches!(e, Entry::Other(t) if *t == text))
+                {
+                    entries.push(Entry::Other(text));
+                }
+            }
+        }
+    }
+
+    let mut res = String::new();
+    for attr in inner_attrs {
+        res += attr;
+        res.push('\n');
+    }
+    for entry in entries {
+        match entry {
+            Entry::Use(i) => {
+                let (ref head, ref root) = groups[i];
+                // e.g. `use foo as bar;`
+                for leaf in &root.leaves {
+                    res += &format!("{}{};\n", head, leaf);
+                }
+                for (name, child) in &root.children {
+                    res += &format!("{}{};\n", head, child.render(name));
+                }
+            }
+            Entry::Other(text) => {
+                res += text;
+                res.push('\n');
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::{merge_prefixes, use_leaves};
+
+    #[test]
+    fn test_merge_prefixes() {
+        let prefixes = [
+            "use std::io::Read;",
+            "const MOD: u64 = 1_000_000_007;",
+            "use std::io::Read;\nuse std::str::FromStr;",
+            "use std::io::{self, Write as _};",
+            "use std::collections::*;",
+            "const MOD: u64 = 1_000_000_007;",
+            "pub use std::cmp::max;",
+            "use std::cmp::{min, max};",
+            "use rand;",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            merge_prefixes(&prefixes),
+            "use rand;\n\
+             use std::{cmp::{max, min}, collections::*, io::{self, Read, Write as _}, str::FromStr};\n\
+             const MOD: u64 = 1_000_000_007;\n\
+             pub use std::cmp::max;\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_prefixes_order() {
+        let prefixes = [
+            "extern crate rand;\nuse rand::Rng;",
+            "#[allow(unused)]\nconst A: u64 = 1;\nuse std::io::Read;",
+            "const B: u64 = A;\nuse rand::random;\nextern crate rand;",
+            "#![allow(dead_code)]\nuse std::io::Write;",
+            "#![allow(dead_code)]\n#![allow(unused_macros)]",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+        // Inner attributes are moved to the top, where they are allowed.
+        let merged = merge_prefixes(&prefixes);
+        assert_eq!(
+            merged,
+            "#![allow(dead_code)]\n\
+             #![allow(unused_macros)]\n\
+             extern crate rand;\n\
+             use rand::{Rng, random};\n\
+             use std::io::{Read, Write};\n\
+             #[allow(unused)]\n\
+             const A: u64 = 1;\n\
+             const B: u64 = A;\n"
+        );
+        assert!(syn::parse_file(&merged).is_ok());
+    }
+
+    #[test]
+    fn test_merge_prefixes_groups() {
+        let prefixes = [
+            "use std::io",
+            "use std::io",
+            "use ::std::fmt;",
+            "pub use std::fs;",
+            "use std::fmt;",
+            "use foo as bar;",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+        // Unparsable text is kept as is. Imports with a different head aren't merged.
+        assert_eq!(
+            merge_prefixes(&prefixes),
+            "use std::io\n\
+             use ::std::fmt;\n\
+             pub use std::fs;\n\
+             use foo as bar;\n\
+             use std::fmt;\n"
+        );
+        assert_eq!(merge_prefixes(&[]), "");
+    }
+
+    #[test]
+    fn test_use_leaves() {
+        let leaves = |src: &str| use_leaves(&syn::parse_str(src).unwrap());
+        let leaf = |path: &[&str], last: &str, decl: &str| {
+            (
+                path.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+                last.to_string(),
+                decl.to_string(),
+            )
+        };
+
+        assert_eq!(
+            leaves("pub use ::std::io::{self, Read as R, prelude::*};"),
+            vec![
+                leaf(&["std", "io"], "self", "pub use ::std::io::{self};"),
+                leaf(&["std", "io"], "Read as R", "pub use ::std::io::Read as R;"),
+                leaf(
+                    &["std", "io", "prelude"],
+                    "*",
+                    "pub use ::std::io::prelude::*;"
+                ),
+            ]
+        );
+        assert_eq!(leaves("use rand;"), vec![leaf(&[], "rand", "use rand;")]);
+        assert!(leaves("use std::{};").is_empty());
+    }
+}
@@ -1,10 +1,261 @@
 extern crate proc_macro;
 
-use crate::proc_macro::TokenStream;
+mod schema;
 
+use crate::proc_macro::{
+    Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree,
+};
+use crate::schema::ValueKind;
+
+/// Mark an item as a snippet.
+///
+/// Arguments are checked against what `cargo snippet` understands.
+///
+/// ```
+/// use cargo_snippet::snippet;
+///
+/// #[snippet("mymath", name = "gcd", include = "lcm", prefix = "use std::io;", doc_hidden)]
+/// fn gcd(a: u64, b: u64) -> u64 {
+///     if b == 0 { a } else { gcd(b, a % b) }
+/// }
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// use cargo_snippet::snippet;
+///
+/// #[snippet(inculde = "gcd")]
+/// fn lcm() {}
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// use cargo_snippet::snippet;
+///
+/// #[snippet(doc_hidden = "yes", prefix)]
+/// fn lcm() {}
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// use cargo_snippet::snippet;
+///
+/// #[snippet("")]
+/// fn lcm() {}
+/// # fn main() {}
+/// ```
+#[proc_macro_attribute]
+pub fn snippet(attr: TokenStream, item: TokenStream) -> TokenStream {
+    // The item is emitted as is. `cargo snippet` reads the source.
+    let mut res = validate(attr)
+        .into_iter()
+        .map(|(msg, span)| compile_error(&msg, span))
+        .collect::<TokenStream>();
+    res.extend(item);
+    res
+}
+
+/// Exclude an item from the enclosing snippet. e.g. a debug helper in a `#[snippet] mod`.
+/// The item is still compiled as usual. `#[snippet(skip)]` does the same.
+///
+/// ```
+/// use cargo_snippet::snippet;
+///
+/// #[snippet]
+/// mod math {
+///     pub fn gcd(a: u64, b: u64) -> u64 {
+///         if b == 0 { a } else { gcd(b, a % b) }
+///     }
+///
+///     #[cargo_snippet::snippet_skip]
+///     pub fn dump(a: u64) {
+///         eprintln!("{}", a);
+///     }
+/// }
+/// # fn main() { math::dump(math::gcd(4, 6)); }
+/// ```
+///
+/// ```compile_fail
+/// use cargo_snippet::snippet_skip;
+///
+/// #[snippet_skip("gcd")]
+/// fn dump() {}
+/// # fn main() {}
+/// ```
 #[proc_macro_attribute]
-pub fn snippet(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    // Just register `snippet` attribute.
-    // We do nothing here.
-    item
+pub fn snippet_skip(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut res = match attr.into_iter().next() {
+        Some(first) => compile_error("`snippet_skip` doesn't take arguments", first.span()),
+        None => TokenStream::new(),
+    };
+    res.extend(item);
+    res
+}
+
+/// Mark statements as a snippet. The statements are expanded as they are,
+/// so they can be compiled and tested in place.
+///
+/// Arguments are the same as `#[snippet(...)]` but a name is required.
+///
+/// ```
+/// use cargo_snippet::snippet_block;
+///
+/// fn main() {
+///     snippet_block! { name = "parse_n", {
+///         let n: usize = "3".parse().unwrap();
+///     }}
+///     assert_eq!(n, 3);
+/// }
+/// ```
+///
+/// ```compile_fail
+/// use cargo_snippet::snippet_block;
+///
+/// fn main() {
+///     snippet_block! { include = "gcd", {
+///         let n = 3;
+///     }}
+/// }
+/// ```
+#[proc_macro]
+pub fn snippet_block(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter().collect::<Vec<_>>();
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Brace => g.stream(),
+        last => {
+            let span = last.map(|t| t.span()).unwrap_or_else(Span::call_site);
+            return compile_error(
+                "expected `snippet_block! { name = \"...\", { ... } }`",
+                span,
+            );
+        }
+    };
+
+    let args = tokens.into_iter().collect::<TokenStream>();
+    let errors = validate(args.clone());
+    let mut res = if !errors.is_empty() {
+        errors
+            .into_iter()
+            .map(|(msg, span)| compile_error(&msg, span))
+            .collect::<TokenStream>()
+    } else if !has_name(args) {
+        compile_error(
+            "`snippet_block!` requires a snippet name",
+            Span::call_site(),
+        )
+    } else {
+        TokenStream::new()
+    };
+    res.extend(body);
+    res
+}
+
+// Whether `"name"` or `name = "..."` is in arguments. They are assumed to be valid.
+fn has_name(args: TokenStream) -> bool {
+    let tokens = args.into_iter().collect::<Vec<_>>();
+    tokens
+        .split(|t| matches!(t, TokenTree::Punct(ref p) if p.as_char() == ','))
+        .any(|arg| match arg {
+            [TokenTree::Literal(_)] => true,
+            [TokenTree::Ident(ref key), ..] => key.to_string() == "name",
+            _ => false,
+        })
+}
+
+// `compile_error!("msg");` pointing `span`
+fn compile_error(msg: &str, span: Span) -> TokenStream {
+    let mut lit = Literal::string(msg);
+    lit.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenTree::Literal(lit).into());
+    group.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut semi = Punct::new(';', Spacing::Alone);
+    semi.set_span(span);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+        TokenTree::Punct(semi),
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn is_str_literal(lit: &Literal) -> bool {
+    let s = lit.to_string();
+    s.starts_with('"') || s.starts_with("r\"") || s.starts_with("r#")
+}
+
+fn is_empty_str_literal(lit: &Literal) -> bool {
+    let s = lit.to_string();
+    s.trim_start_matches('r').trim_matches('#') == "\"\""
+}
+
+// Check arguments of `#[snippet(...)]`. Returns error messages with their spans.
+fn validate(attr: TokenStream) -> Vec<(String, Span)> {
+    let mut errors = Vec::new();
+    let tokens = attr.into_iter().collect::<Vec<_>>();
+
+    // Split by `,`
+    for arg in tokens.split(|t| matches!(t, TokenTree::Punct(ref p) if p.as_char() == ',')) {
+        match arg {
+            [] => (),
+            [TokenTree::Literal(ref lit)] => {
+                if !is_str_literal(lit) {
+                    errors.push((
+                        "snippet name must be a string literal".to_string(),
+                        lit.span(),
+                    ));
+                } else if is_empty_str_literal(lit) {
+                    errors.push(("snippet name must not be empty".to_string(), lit.span()));
+                }
+            }
+            [TokenTree::Ident(ref key)] => match schema::lookup(&key.to_string()) {
+                Some(ValueKind::Flag) => (),
+                Some(ValueKind::Str) => {
+                    errors.push((format!("`{}` requires a string value", key), key.span()))
+                }
+                None => errors.push((unknown_key(&key.to_string()), key.span())),
+            },
+            [TokenTree::Ident(ref key), TokenTree::Punct(ref eq), value @ ..]
+                if eq.as_char() == '=' =>
+            {
+                let key_str = key.to_string();
+                match (schema::lookup(&key_str), value) {
+                    (Some(ValueKind::Str), [TokenTree::Literal(ref lit)])
+                        if is_str_literal(lit) =>
+                    {
+                        if key_str == "name" && is_empty_str_literal(lit) {
+                            let msg = "snippet name must not be empty".to_string();
+                            errors.push((msg, lit.span()));
+                        }
+                    }
+                    (Some(ValueKind::Str), _) => errors.push((
+                        format!("value of `{}` must be a string literal", key_str),
+                        value.first().map(|t| t.span()).unwrap_or_else(|| eq.span()),
+                    )),
+                    (Some(ValueKind::Flag), _) => {
+                        errors.push((format!("`{}` doesn't take a value", key_str), key.span()))
+                    }
+                    (None, _) => errors.push((unknown_key(&key_str), key.span())),
+                }
+            }
+            [first, ..] => errors.push((
+                "expected `\"name\"`, `key = \"value\"` or `flag`".to_string(),
+                first.span(),
+            )),
+        }
+    }
+
+    errors
+}
+
+fn unknown_key(key: &str) -> String {
+    format!(
+        "unknown snippet attribute `{}`. Expected one of {}",
+        key,
+        schema::key_list()
+    )
 }
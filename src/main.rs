@@ -1,11 +1,28 @@
+mod analysis;
+mod bundle;
+mod cfg;
+mod check;
 mod config;
+mod conflict;
+mod diagnostics;
+mod export;
+mod filter;
 mod fsutil;
+mod imports;
+mod modtree;
 mod parser;
+mod paths;
+mod placeholder;
+mod prune;
+mod schema;
 mod snippet;
+mod source;
+mod subst;
 mod writer;
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Read;
+use std::io::{self, Read};
 
 use clap::{crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
 use log::error;
@@ -37,37 +54,294 @@ fn main() {
                 .about("Extract code snippet from cargo projects")
                 .arg(Arg::with_name("PATH").multiple(true).help(
                     "The files or directories (including children) \
-                     to extract snippet (defaults to <project_root>/src when omitted)",
+                     to extract snippet (defaults to the modules of the crate when omitted)",
                 ))
                 .arg(
                     Arg::with_name("output_type")
                         .long("type")
                         .short("t")
                         .default_value("neosnippet")
-                        .possible_values(&["neosnippet", "vscode", "ultisnips"]),
+                        .possible_values(&["neosnippet", "vscode", "ultisnips", "yasnippet", "luasnip", "vsnip", "jetbrains", "sublime", "zed", "json", "ndjson"]),
+                )
+                .arg(
+                    Arg::with_name("out_dir")
+                        .long("out-dir")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .help("Directory to write snippets for directory-based formats (yasnippet, sublime)"),
+                )
+                .arg(
+                    Arg::with_name("verbatim")
+                        .long("verbatim")
+                        .global(true)
+                        .help("Keep comments and layout of the source without rustfmt"),
+                )
+                .arg(
+                    Arg::with_name("infer_deps")
+                        .long("infer-deps")
+                        .global(true)
+                        .help("Infer dependencies between snippets from identifier usage"),
+                )
+                .arg(
+                    Arg::with_name("features")
+                        .long("features")
+                        .global(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("FEATURES")
+                        .help("Evaluate `cfg` with these features enabled (comma or space separated)"),
+                )
+                .arg(
+                    Arg::with_name("cfg")
+                        .long("cfg")
+                        .global(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("SPEC")
+                        .help("Evaluate `cfg` with this option set, e.g. `unix` or `target_os=\"linux\"`"),
+                )
+                .arg(
+                    Arg::with_name("subst")
+                        .long("subst")
+                        .global(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME=VALUE")
+                        .help("Substitute a generic parameter or const in all snippets"),
+                )
+                .arg(
+                    Arg::with_name("wrap_mod")
+                        .long("wrap-mod")
+                        .global(true)
+                        .help("Emit snippets inside modules mirroring where they are defined"),
+                )
+                .subcommand(
+                    SubCommand::with_name("bundle")
+                        .about("Expand snippets imported by a solution file into one file")
+                        .arg(
+                            Arg::with_name("MAIN")
+                                .required(true)
+                                .help("The solution file which `use`s the snippet library"),
+                        )
+                        .arg(
+                            Arg::with_name("lib")
+                                .long("lib")
+                                .short("l")
+                                .takes_value(true)
+                                .multiple(true)
+                                .help(
+                                    "The files or directories of the snippet library \
+                                     (defaults to <project_root>/src)",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("crate")
+                                .long("crate")
+                                .short("c")
+                                .takes_value(true)
+                                .multiple(true)
+                                .help(
+                                    "The crate name of the snippet library \
+                                     (defaults to the package name of <project_root>/Cargo.toml)",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("prune")
+                                .long("prune")
+                                .help("Remove snippet items which are not used by the solution"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Compile every snippet standalone to verify it is self-contained")
+                        .arg(Arg::with_name("PATH").multiple(true).help(
+                            "The files or directories (including children) \
+                             to extract snippet (defaults to the modules of the crate when omitted)",
+                        ))
+                        .arg(
+                            Arg::with_name("edition")
+                                .long("edition")
+                                .takes_value(true)
+                                .possible_values(&["2015", "2018", "2021", "2024"])
+                                .help(
+                                    "The edition to compile snippets with \
+                                     (defaults to the edition in <project_root>/Cargo.toml)",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("lint")
+                        .about("Report problems of snippets such as conflicting definitions")
+                        .arg(Arg::with_name("PATH").multiple(true).help(
+                            "The files or directories (including children) \
+                             to extract snippet (defaults to the modules of the crate when omitted)",
+                        )),
                 ),
         )
         .get_matches();
 
     let config = config::Config::from_matches(&matches);
+    let tree = config.target.module_tree();
+    let parse_options = parser::ParseOptions {
+        verbatim: config.verbatim,
+        wrap_mod: config.wrap_mod,
+        subst: config.subst.clone(),
+        cfg: config.cfg.clone(),
+        modules: tree.modules,
+    };
 
+    let mut diagnostics = Vec::new();
+    let snippets = load_snippets(&tree.files, &parse_options, &mut diagnostics);
+
+    let inferred = if config.infer_deps {
+        analysis::infer_dependencies(&snippets)
+    } else {
+        BTreeMap::new()
+    };
+    for (name, deps) in &inferred {
+        for dep in deps {
+            eprintln!("inferred dependency: {} -> {}", name, dep);
+        }
+    }
+
+    diagnostics.extend(snippet::diagnose(&snippets, &inferred));
+    diagnostics.extend(conflict::diagnose(&snippets, &inferred));
+    let has_error = diagnostics::emit(&diagnostics);
+
+    match config.command {
+        config::Command::Extract => {
+            let processed = snippet::process_snippets(&snippets, &inferred);
+            let verbatim = snippet::verbatim_names(&snippets);
+            let formatted = if config.verbatim {
+                processed
+            } else {
+                writer::format_snippets(&processed, &verbatim)
+            };
+
+            match config.format {
+                config::Format::Index(ref index_format) => {
+                    // rustfmt isn't applied with `--verbatim` or to `verbatim` snippets
+                    let mut formatted = if config.verbatim {
+                        BTreeMap::new()
+                    } else {
+                        formatted
+                    };
+                    formatted.retain(|name, _| !verbatim.contains(name));
+                    let index = export::Index::new(&snippets, &inferred, &formatted);
+                    report_error(index_format.write(&index, io::stdout().lock()));
+                }
+                config::Format::Editor(ref output_type) => {
+                    report_error(output_type.write(&formatted, config.out_dir));
+                }
+            }
+        }
+        config::Command::Check { ref edition } => {
+            let verbatim = snippet::verbatim_names(&snippets);
+            let snippets = snippet::process_snippets(&snippets, &inferred);
+            // Formatted for readable error messages
+            let snippets = if config.verbatim {
+                snippets
+            } else {
+                writer::format_snippets(&snippets, &verbatim)
+            };
+            let failures =
+                match report_error(check::check_snippets(&snippets, edition, |name, ok| {
+                    eprintln!("checking {} ... {}", name, if ok { "ok" } else { "FAILED" });
+                })) {
+                    Some(failures) => failures,
+                    None => std::process::exit(1),
+                };
+            for failure in &failures {
+                eprintln!("\n---- {} ----\n{}", failure.name, failure.stderr);
+            }
+            if !failures.is_empty() {
+                eprintln!(
+                    "error: {} of {} snippets failed to compile on their own",
+                    failures.len(),
+                    snippets.len()
+                );
+                std::process::exit(1);
+            }
+        }
+        config::Command::Lint => {
+            // Warnings are also problems here.
+            if !diagnostics.is_empty() {
+                let n = diagnostics.len();
+                eprintln!(
+                    "error: {} problem{} found",
+                    n,
+                    if n == 1 { "" } else { "s" }
+                );
+                std::process::exit(1);
+            }
+        }
+        config::Command::Bundle {
+            main,
+            ref crate_names,
+            prune,
+        } => {
+            if crate_names.is_empty() {
+                error!("The crate name of the snippet library is unknown. Specify it by --crate.");
+                std::process::exit(1);
+            }
+            let graph = snippet::SnippetGraph::new(&snippets, &inferred);
+            let src = match report_error(fs::read_to_string(main)) {
+                Some(src) => src,
+                None => std::process::exit(1),
+            };
+            let format = |s: &str| {
+                if config.verbatim {
+                    None
+                } else {
+                    writer::format_src(s)
+                }
+            };
+            match report_error(bundle::bundle(&src, crate_names, &graph, prune, format)) {
+                Some(bundled) => {
+                    log::info!("Bundled snippets: {}", bundled.resolution.order.join(", "));
+                    for warning in &bundled.warnings {
+                        eprintln!("warning: {}", warning);
+                    }
+                    for name in &bundled.unresolved {
+                        eprintln!("warning: snippet `{}` is not found", name);
+                    }
+                    print!("{}", placeholder::strip(&bundled.code));
+                }
+                None => std::process::exit(1),
+            }
+        }
+    }
+
+    if has_error {
+        std::process::exit(1);
+    }
+}
+
+// Parse all target files.
+fn load_snippets(
+    files: &[modtree::ModuleFile],
+    parse_options: &parser::ParseOptions,
+    diagnostics: &mut Vec<diagnostics::Diagnostic>,
+) -> Vec<snippet::Snippet> {
     // Alphabetical order
     let mut snippets = Vec::new();
 
     let mut buf = String::new();
-    for path in config.target.iter_paths() {
+    for module_file in files {
         buf.clear();
-        log::info!("Start read {:?}", &path);
-        if let Some(mut file) = report_error(fs::File::open(path)) {
+        log::info!("Start read {:?}", &module_file.path);
+        if let Some(mut file) = report_error(fs::File::open(&module_file.path)) {
             if report_error(file.read_to_string(&mut buf)).is_some() {
-                if let Some(mut parsed) = report_error(parser::parse_snippet(&buf)) {
-                    snippets.append(&mut parsed);
-                }
+                let (mut parsed, mut diags) =
+                    parser::parse_snippet(&buf, Some(module_file), parse_options);
+                snippets.append(&mut parsed);
+                diagnostics.append(&mut diags);
             }
         }
     }
 
-    config
-        .output_type
-        .write(&snippet::process_snippets(&snippets));
+    snippets
 }
@@ -0,0 +1,321 @@
+use syn::{Attribute, Item, Lit, Meta};
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A source file and where it is in the module tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleFile {
+    pub path: PathBuf,
+    // e.g. `["math", "modint"]` for `crate::math::modint`. Empty for the crate root or unknown.
+    pub module_path: Vec<String>,
+}
+
+impl ModuleFile {
+    // A file whose position in the module tree is unknown.
+    pub fn new(path: PathBuf) -> Self {
+        ModuleFile {
+            path,
+            module_path: Vec::new(),
+        }
+    }
+}
+
+// Files and modules of a crate.
+#[derive(Debug, Default)]
+pub struct ModuleTree {
+    pub files: Vec<ModuleFile>,
+    // Paths of all modules including inline ones. Empty if the module tree is unknown.
+    pub modules: BTreeSet<Vec<String>>,
+}
+
+impl ModuleTree {
+    // Files whose positions in the module tree are unknown.
+    pub fn from_files(files: impl IntoIterator<Item = PathBuf>) -> Self {
+        ModuleTree {
+            files: files.into_iter().map(ModuleFile::new).collect(),
+            modules: BTreeSet::new(),
+        }
+    }
+
+    // Add files and modules of `other`. Files already added are skipped.
+    pub fn merge(&mut self, other: ModuleTree) {
+        for file in other.files {
+            if !self.files.iter().any(|f| f.path == file.path) {
+                self.files.push(file);
+            }
+        }
+        self.modules.extend(other.modules);
+    }
+}
+
+// `crate::a::b` for `["a", "b"]`
+pub fn display_module_path(module_path: &[String]) -> String {
+    Some("crate")
+        .into_iter()
+        .chain(module_path.iter().map(|s| s.as_str()))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+// Value of `#[path = "..."]`
+fn path_attr(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(ref nv)) if nv.path.is_ident("path") => match nv.lit {
+            Lit::Str(ref s) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// Collect files reachable from the crate root `root` by following `mod foo;` declarations.
+// Files are in the order of declarations, starting with `root`.
+pub fn walk(root: &Path) -> ModuleTree {
+    let mut walker = Walker {
+        visited: BTreeSet::new(),
+        tree: ModuleTree::default(),
+    };
+    walker.visit_file(root.to_path_buf(), Vec::new(), true);
+    walker.tree
+}
+
+struct Walker {
+    visited: BTreeSet<PathBuf>,
+    tree: ModuleTree,
+}
+
+impl Walker {
+    // `is_mod_rs` is true for the crate root, `mod.rs` and files specified by `#[path]`.
+    // Child modules of other files (e.g. `foo.rs`) are in a directory named after the file (`foo/`).
+    fn visit_file(&mut self, path: PathBuf, module_path: Vec<String>, is_mod_rs: bool) {
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if !self.visited.insert(canonical) {
+            return;
+        }
+
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) => {
+                log::warn!("Failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        self.tree.modules.insert(module_path.clone());
+        self.tree.files.push(ModuleFile {
+            path: path.clone(),
+            module_path: module_path.clone(),
+        });
+
+        // Parse errors are reported when snippets are extracted.
+        let file = match syn::parse_file(&src) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mod_dir = match path.file_stem() {
+            Some(stem) if !is_mod_rs => dir.join(stem),
+            _ => dir.clone(),
+        };
+        self.visit_items(&file.items, &dir, &mod_dir, &module_path);
+    }
+
+    // `path_dir` is the base of `#[path]` and `mod_dir` is the directory of child modules.
+    fn visit_items(
+        &mut self,
+        items: &[Item],
+        path_dir: &Path,
+        mod_dir: &Path,
+        module_path: &[String],
+    ) {
+        for item in items {
+            let item_mod = match item {
+                Item::Mod(ref item_mod) => item_mod,
+                _ => continue,
+            };
+            let name = item_mod.ident.to_string();
+            let mut child_path = module_path.to_vec();
+            child_path.push(name.clone());
+            let path = path_attr(&item_mod.attrs);
+            self.tree.modules.insert(child_path.clone());
+
+            match item_mod.content {
+                Some((_, ref items)) => {
+                    let dir = mod_dir.join(path.unwrap_or(name));
+                    self.visit_items(items, &dir, &dir, &child_path);
+                }
+                None => {
+                    let candidates = match path {
+                        Some(ref path) => vec![path_dir.join(path)],
+                        None => vec![
+                            mod_dir.join(format!("{}.rs", name)),
+                            mod_dir.join(&name).join("mod.rs"),
+                        ],
+                    };
+                    match candidates.into_iter().find(|p| p.is_file()) {
+                        Some(file) => {
+                            let is_mod_rs =
+                                path.is_some() || file.file_name() == Some(OsStr::new("mod.rs"));
+                            self.visit_file(file, child_path, is_mod_rs);
+                        }
+                        None => log::warn!(
+                            "File of module {} is not found",
+                            display_module_path(&child_path)
+                        ),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{display_module_path, walk, ModuleFile, ModuleTree};
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // Write `files` in a temporary directory named after `name`
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "cargo-snippet-modtree-{}-{}",
+            name,
+            std::process::id()
+        ));
+        for (path, content) in files.iter() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn module(dir: &Path, path: &str, module_path: &[&str]) -> ModuleFile {
+        ModuleFile {
+            path: dir.join(Path::new(path)),
+            module_path: module_path.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn modules(tree: &ModuleTree) -> Vec<String> {
+        tree.modules.iter().map(|m| m.join("::")).collect()
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = write_files(
+            "walk",
+            &[
+                (
+                    "src/lib.rs",
+                    "mod a; #[path = \"other/c.rs\"] mod c; mod d { mod e; }",
+                ),
+                ("src/a.rs", "mod b;"),
+                ("src/a/b.rs", ""),
+                ("src/other/c.rs", "mod f;"),
+                ("src/other/f/mod.rs", ""),
+                ("src/d/e.rs", ""),
+                ("src/stray.rs", ""),
+            ],
+        );
+
+        let tree = walk(&dir.join("src/lib.rs"));
+        let expected = vec![
+            module(&dir, "src/lib.rs", &[]),
+            module(&dir, "src/a.rs", &["a"]),
+            module(&dir, "src/a/b.rs", &["a", "b"]),
+            module(&dir, "src/other/c.rs", &["c"]),
+            module(&dir, "src/other/f/mod.rs", &["c", "f"]),
+            module(&dir, "src/d/e.rs", &["d", "e"]),
+        ];
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(tree.files, expected);
+        assert_eq!(
+            modules(&tree),
+            vec!["", "a", "a::b", "c", "c::f", "d", "d::e"]
+        );
+    }
+
+    #[test]
+    fn test_walk_missing() {
+        let dir = write_files(
+            "missing",
+            &[
+                ("src/main.rs", "mod a; mod broken; #[path = 1] mod c;"),
+                ("src/broken.rs", "mod b; fn"),
+                ("src/broken/b.rs", ""),
+            ],
+        );
+
+        let tree = walk(&dir.join("src/main.rs"));
+        let missing_root = walk(&dir.join("src/lib.rs"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Modules are known even if their files are missing or unparsable.
+        assert_eq!(
+            tree.files,
+            vec![
+                module(&dir, "src/main.rs", &[]),
+                module(&dir, "src/broken.rs", &["broken"]),
+            ]
+        );
+        assert_eq!(modules(&tree), vec!["", "a", "broken", "c"]);
+        assert!(missing_root.files.is_empty());
+        assert!(missing_root.modules.is_empty());
+    }
+
+    #[test]
+    fn test_walk_cycle() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("src/lib.rs", "mod a;"),
+                ("src/a.rs", "#[path = \"../lib.rs\"] mod root;"),
+            ],
+        );
+
+        let tree = walk(&dir.join("src/lib.rs"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            tree.files,
+            vec![
+                module(&dir, "src/lib.rs", &[]),
+                module(&dir, "src/a.rs", &["a"])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut tree = ModuleTree::from_files(vec![PathBuf::from("a.rs")]);
+        let mut other = ModuleTree::from_files(vec![PathBuf::from("b.rs"), PathBuf::from("a.rs")]);
+        other.modules.insert(vec!["b".to_string()]);
+        other.files[1].module_path = vec!["a".to_string()];
+        tree.merge(other);
+
+        // The first one is kept.
+        assert_eq!(
+            tree.files,
+            vec![
+                ModuleFile::new(PathBuf::from("a.rs")),
+                ModuleFile::new(PathBuf::from("b.rs")),
+            ]
+        );
+        assert_eq!(modules(&tree), vec!["b"]);
+    }
+
+    #[test]
+    fn test_display_module_path() {
+        assert_eq!(display_module_path(&[]), "crate");
+        assert_eq!(
+            display_module_path(&["a".to_string(), "b".to_string()]),
+            "crate::a::b"
+        );
+    }
+}
@@ -1,11 +1,29 @@
 use lazy_static::lazy_static;
-use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
 use quote::ToTokens;
 use regex::{Captures, Regex};
-use syn::{parse_file, Attribute, File, Item, Meta, MetaList, NestedMeta};
-
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::visit_mut;
+use syn::{
+    parse_file, parse_quote, Attribute, Block, File, ImplItem, Item, ItemImpl, ItemMod, ItemTrait,
+    Lit, Macro, Meta, MetaList, NestedMeta, TraitItem, Visibility,
+};
+
+use crate::cfg::Cfg;
+use crate::diagnostics::{Diagnostic, Location};
+use crate::filter::{self, ContentFilter};
+use crate::modtree::ModuleFile;
+use crate::paths::PathRewriter;
+use crate::placeholder;
+use crate::schema::{self, ValueKind};
 use crate::snippet::{Snippet, SnippetAttributes};
-use std::collections::HashSet;
+use crate::source::{dedent, Edit, Source};
+use crate::subst::{self, substitute};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range;
+use std::path::Path;
 use std::{char, u32};
 
 fn is_snippet_path(path: &str) -> bool {
@@ -15,6 +33,10 @@ fn is_snippet_path(path: &str) -> bool {
     }
 }
 
+fn is_snippet_block_path(path: &str) -> bool {
+    matches!(path, "snippet_block" | "cargo_snippet :: snippet_block")
+}
+
 macro_rules! get_attrs_impl {
     ($arg: expr, $($v: path), *) => {
         {
@@ -50,6 +72,28 @@ fn get_attrs(item: &Item) -> Option<&Vec<Attribute>> {
     )
 }
 
+fn get_impl_item_attrs(item: &ImplItem) -> Option<&Vec<Attribute>> {
+    // All ImplItem variants except ImplItem::Verbatim
+    get_attrs_impl!(
+        item,
+        ImplItem::Const,
+        ImplItem::Method,
+        ImplItem::Type,
+        ImplItem::Macro
+    )
+}
+
+fn get_trait_item_attrs(item: &TraitItem) -> Option<&Vec<Attribute>> {
+    // All TraitItem variants except TraitItem::Verbatim
+    get_attrs_impl!(
+        item,
+        TraitItem::Const,
+        TraitItem::Method,
+        TraitItem::Type,
+        TraitItem::Macro
+    )
+}
+
 macro_rules! remove_snippet_attr_impl {
     ($arg: expr, $($v: path), *) => {
         {
@@ -87,13 +131,48 @@ fn remove_snippet_attr(item: &mut Item) {
         Item::Macro2
     );
 
-    if let Item::Mod(ref mut item_mod) = item {
-        if let Some(&mut (_, ref mut items)) = item_mod.content.as_mut() {
-            items.iter_mut().for_each(|item| remove_snippet_attr(item));
+    match item {
+        Item::Mod(ref mut item_mod) => {
+            if let Some(&mut (_, ref mut items)) = item_mod.content.as_mut() {
+                items.iter_mut().for_each(|item| remove_snippet_attr(item));
+            }
+        }
+        Item::Impl(ref mut item_impl) => {
+            item_impl
+                .items
+                .iter_mut()
+                .for_each(remove_snippet_attr_impl_item);
+        }
+        Item::Trait(ref mut item_trait) => {
+            item_trait
+                .items
+                .iter_mut()
+                .for_each(remove_snippet_attr_trait_item);
         }
+        _ => (),
     }
 }
 
+fn remove_snippet_attr_impl_item(item: &mut ImplItem) {
+    remove_snippet_attr_impl!(
+        item,
+        ImplItem::Const,
+        ImplItem::Method,
+        ImplItem::Type,
+        ImplItem::Macro
+    );
+}
+
+fn remove_snippet_attr_trait_item(item: &mut TraitItem) {
+    remove_snippet_attr_impl!(
+        item,
+        TraitItem::Const,
+        TraitItem::Method,
+        TraitItem::Type,
+        TraitItem::Macro
+    );
+}
+
 fn unquote(s: &str) -> String {
     let chars: Vec<char> = s.chars().collect();
 
@@ -133,6 +212,24 @@ fn get_default_snippet_name(item: &Item) -> Option<String> {
     )
 }
 
+fn get_default_snippet_name_impl_item(item: &ImplItem) -> Option<String> {
+    match item {
+        ImplItem::Const(ref x) => Some(x.ident.to_string()),
+        ImplItem::Method(ref x) => Some(x.sig.ident.to_string()),
+        ImplItem::Type(ref x) => Some(x.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn get_default_snippet_name_trait_item(item: &TraitItem) -> Option<String> {
+    match item {
+        TraitItem::Const(ref x) => Some(x.ident.to_string()),
+        TraitItem::Method(ref x) => Some(x.sig.ident.to_string()),
+        TraitItem::Type(ref x) => Some(x.ident.to_string()),
+        _ => None,
+    }
+}
+
 fn get_snippet_name(attr: &Attribute) -> Option<String> {
     attr.parse_meta().ok().and_then(|metaitem| {
         if !is_snippet_path(metaitem.path().to_token_stream().to_string().as_str()) {
@@ -217,15 +314,12 @@ fn get_simple_attr(attr: &Attribute, key: &str) -> Vec<String> {
                     .iter()
                     .filter_map(|item| {
                         if let NestedMeta::Meta(Meta::NameValue(ref nv)) = item {
-                            if nv.path.to_token_stream().to_string() == key {
-                                let value = if let syn::Lit::Str(s) = &nv.lit.clone() {
-                                    s.value()
-                                } else {
-                                    panic!("attribute must be string");
-                                };
-                                Some(value)
-                            } else {
-                                None
+                            match nv.lit {
+                                // Non-string values are reported by `validate_attr`.
+                                Lit::Str(ref s) if nv.path.to_token_stream().to_string() == key => {
+                                    Some(s.value())
+                                }
+                                _ => None,
                             }
                         } else {
                             None
@@ -240,9 +334,14 @@ fn get_simple_attr(attr: &Attribute, key: &str) -> Vec<String> {
 }
 
 fn parse_attrs(
+    ctx: &Context,
     attrs: &[Attribute],
     default_snippet_name: Option<String>,
 ) -> Option<SnippetAttributes> {
+    for attr in attrs {
+        validate_attr(ctx, attr);
+    }
+
     let meta_parsed = attrs
         .iter()
         .filter_map(|a| a.parse_meta().ok())
@@ -258,6 +357,10 @@ fn parse_attrs(
     {
         return None;
     }
+    // Marked to be excluded from the enclosing snippet
+    if attrs.iter().any(filter::is_skip_attr) {
+        return None;
+    }
 
     let mut names = attrs
         .iter()
@@ -271,25 +374,29 @@ fn parse_attrs(
         matches!(meta, Meta::Path(_))
     });
 
-    if attr_snippet_without_value {
-        if let Some(ref default) = default_snippet_name {
-            names.insert(default.clone());
-        }
+    let default_name = if attr_snippet_without_value || names.is_empty() {
+        default_snippet_name
+    } else {
+        None
+    };
+    if let Some(ref default) = default_name {
+        names.insert(default.clone());
     }
 
     if names.is_empty() {
-        if let Some(default) = default_snippet_name {
-            names.insert(default);
-        } else {
-            return None;
-        }
+        return None;
     }
 
-    let uses = attrs
+    let mut uses = Vec::new();
+    for dep in attrs
         .iter()
         .filter_map(get_snippet_uses)
         .flat_map(|v| v.into_iter())
-        .collect::<HashSet<_>>();
+    {
+        if !uses.contains(&dep) {
+            uses.push(dep);
+        }
+    }
 
     let prefix = attrs
         .iter()
@@ -298,26 +405,147 @@ fn parse_attrs(
         .collect::<Vec<_>>()
         .join("\n");
 
-    let doc_hidden = meta_parsed.iter().any(|(meta, is_snippet_path)| {
+    let mut placeholders = Vec::new();
+    for name in attrs
+        .iter()
+        .flat_map(|attr| get_simple_attr(attr, "placeholder"))
+        .flat_map(|s| {
+            s.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+        })
+    {
+        if !placeholders.contains(&name) {
+            placeholders.push(name);
+        }
+    }
+
+    // Options take precedence over attributes.
+    let mut subst = attrs
+        .iter()
+        .flat_map(|attr| get_simple_attr(attr, "subst"))
+        .flat_map(|s| subst::parse_subst(&s))
+        .collect::<Vec<_>>();
+    for (key, value) in &ctx.options.subst {
+        subst.retain(|(k, _)| k != key);
+        subst.push((key.clone(), value.clone()));
+    }
+
+    let doc_hidden = has_flag(&meta_parsed, "doc_hidden");
+    let wrap_impl = has_flag(&meta_parsed, "wrap_impl");
+    let verbatim = has_flag(&meta_parsed, "verbatim");
+    let wrap_mod = has_flag(&meta_parsed, "wrap_mod") || ctx.options.wrap_mod;
+    let keep_tests = has_flag(&meta_parsed, "keep_tests");
+
+    Some(SnippetAttributes {
+        names,
+        default_name,
+        uses,
+        prefix,
+        placeholders,
+        subst,
+        doc_hidden,
+        wrap_impl,
+        verbatim,
+        wrap_mod,
+        keep_tests,
+    })
+}
+
+// Whether `#[snippet(`flag`)]` is present.
+fn has_flag(meta_parsed: &[(Meta, bool)], flag: &str) -> bool {
+    meta_parsed.iter().any(|(meta, is_snippet_path)| {
         if !is_snippet_path {
             return false;
         }
         match meta {
             Meta::List(MetaList { ref nested, .. }) => nested.iter().any(|n|
-                matches!(n, NestedMeta::Meta(Meta::Path(ref p)) if p.to_token_stream().to_string() == "doc_hidden")
+                matches!(n, NestedMeta::Meta(Meta::Path(ref p)) if p.to_token_stream().to_string() == flag)
             ),
             _ => false,
         }
-    });
-
-    Some(SnippetAttributes {
-        names,
-        uses,
-        prefix,
-        doc_hidden,
     })
 }
 
+// Report malformed snippet attributes, unknown keys and non-string values.
+fn validate_attr(ctx: &Context, attr: &Attribute) {
+    if !is_snippet_path(attr.path.to_token_stream().to_string().as_str()) {
+        return;
+    }
+
+    let list = match attr.parse_meta() {
+        Err(e) => {
+            ctx.report(
+                Diagnostic::error(format!("malformed snippet attribute: {}", e)),
+                e.span(),
+            );
+            return;
+        }
+        Ok(Meta::Path(_)) => return,
+        Ok(Meta::NameValue(nv)) => {
+            if !matches!(nv.lit, Lit::Str(_)) {
+                ctx.report(
+                    Diagnostic::error("snippet name must be a string literal"),
+                    nv.lit.span(),
+                );
+            }
+            return;
+        }
+        Ok(Meta::List(list)) => list,
+    };
+
+    for nested in &list.nested {
+        let diag = match nested {
+            NestedMeta::Lit(Lit::Str(ref s)) if s.value().is_empty() => {
+                Diagnostic::error("snippet name must not be empty")
+            }
+            NestedMeta::Lit(Lit::Str(_)) => continue,
+            NestedMeta::Lit(_) => Diagnostic::error("snippet name must be a string literal"),
+            NestedMeta::Meta(Meta::Path(ref path)) => {
+                let key = path.to_token_stream().to_string();
+                match schema::lookup(&key) {
+                    Some(ValueKind::Flag) => continue,
+                    Some(ValueKind::Str) => {
+                        Diagnostic::error(format!("`{}` requires a string value", key))
+                    }
+                    None => unknown_key(&key),
+                }
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) => {
+                let key = nv.path.to_token_stream().to_string();
+                match (schema::lookup(&key), &nv.lit) {
+                    (Some(ValueKind::Str), Lit::Str(ref s))
+                        if key == "name" && s.value().is_empty() =>
+                    {
+                        Diagnostic::error("snippet name must not be empty")
+                    }
+                    (Some(ValueKind::Str), Lit::Str(_)) => continue,
+                    (Some(ValueKind::Str), _) => {
+                        Diagnostic::error(format!("value of `{}` must be a string literal", key))
+                    }
+                    (Some(ValueKind::Flag), _) => {
+                        Diagnostic::error(format!("`{}` doesn't take a value", key))
+                    }
+                    (None, _) => unknown_key(&key),
+                }
+            }
+            NestedMeta::Meta(Meta::List(ref list)) => {
+                unknown_key(&list.path.to_token_stream().to_string())
+            }
+        };
+        ctx.report(diag, nested.span());
+    }
+}
+
+fn unknown_key(key: &str) -> Diagnostic {
+    Diagnostic::error(format!(
+        "unknown snippet attribute `{}`. Expected one of {}",
+        key,
+        schema::key_list()
+    ))
+}
+
 fn next_token_is_doc(token: &TokenTree) -> bool {
     match token {
         TokenTree::Group(ref g) => g.to_string().starts_with("[doc = "),
@@ -400,7 +628,7 @@ fn format_doc_comment(doc_tt: TokenTree, is_inner: bool, doc_hidden: bool) -> Op
         })
 }
 
-fn stringify_tokens(tokens: TokenStream, doc_hidden: bool) -> String {
+pub fn stringify_tokens(tokens: TokenStream, doc_hidden: bool) -> String {
     let mut res = String::new();
     let mut iter = tokens.into_iter().peekable();
     while let Some(tok) = iter.next() {
@@ -459,85 +687,752 @@ fn stringify_tokens(tokens: TokenStream, doc_hidden: bool) -> String {
     res
 }
 
+// Options for extracting snippets.
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    // Cut every snippet out of the original source text as if `verbatim` is specified.
+    pub verbatim: bool,
+    // Emit every snippet inside modules as if `wrap_mod` is specified.
+    pub wrap_mod: bool,
+    // Substitution for all snippets, which overrides `subst` of attributes.
+    pub subst: Vec<(String, String)>,
+    // Evaluate `cfg` and `cfg_attr` with these options if given.
+    pub cfg: Option<Cfg>,
+    // Known modules of the crate, used to rewrite `crate::` paths.
+    pub modules: BTreeSet<Vec<String>>,
+}
+
+// A file being parsed.
+struct Context<'a> {
+    source: Source<'a>,
+    file: Option<&'a Path>,
+    // Module path of the file
+    module_path: &'a [String],
+    options: &'a ParseOptions,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    // Edits to evaluate `cfg` in the source text
+    cfg_edits: Vec<Edit>,
+}
+
+impl<'a> Context<'a> {
+    fn location(&self, span: proc_macro2::Span) -> Location {
+        Location::from_span(&self.source, span, self.file)
+    }
+
+    fn report(&self, diag: Diagnostic, span: proc_macro2::Span) {
+        let diag = diag.at(Some(self.location(span)));
+        self.diagnostics.borrow_mut().push(diag);
+    }
+
+    fn lines(&self, span: proc_macro2::Span) -> Option<(usize, usize)> {
+        Some((span.start().line, span.end().line))
+    }
+
+    // Location of the first snippet attribute
+    fn snippet_location(&self, attrs: &[Attribute]) -> Option<Location> {
+        attrs
+            .iter()
+            .find(|attr| is_snippet_path(attr.path.to_token_stream().to_string().as_str()))
+            .map(|attr| self.location(attr.span()))
+    }
+
+    // `kept` is modules emitted as they are. See `PathRewriter`.
+    // Paths are left as they are if the content is `wrapped` in modules.
+    fn path_rewriter(
+        &self,
+        wrapped: bool,
+        module_path: &'a [String],
+        kept: Vec<Vec<String>>,
+    ) -> PathRewriter<'_> {
+        let mut rewriter = PathRewriter::new(&self.options.modules, module_path, kept);
+        if wrapped {
+            rewriter.disable();
+        }
+        rewriter
+    }
+
+    fn content_filter(&self, attrs: &SnippetAttributes) -> ContentFilter {
+        ContentFilter::new(!attrs.keep_tests)
+    }
+
+    fn is_verbatim(&self, attrs: &SnippetAttributes) -> bool {
+        self.options.verbatim || attrs.verbatim
+    }
+
+    // Original source text of `tokens` which spans `range`.
+    // Snippet attributes (and doc comments if `doc_hidden`) are removed,
+    // `edits` are applied and the rest is kept as is.
+    fn verbatim(
+        &self,
+        tokens: TokenStream,
+        range: Range<usize>,
+        doc_hidden: bool,
+        mut edits: Vec<Edit>,
+    ) -> String {
+        self.collect_attr_edits(tokens, doc_hidden, &mut edits);
+        edits.extend(self.cfg_edits.iter().cloned());
+        let (range, indent) = self.source.expand_to_line_start(range);
+        let mut content = dedent(
+            self.source.slice_with_edits(range, &edits).trim_end(),
+            indent,
+        );
+        content.push('\n');
+        content
+    }
+
+    // Source text of an associated item together with the header of its enclosing block.
+    fn verbatim_wrapped(
+        &self,
+        header: TokenStream,
+        header_range: Range<usize>,
+        item: TokenStream,
+        item_range: Range<usize>,
+        doc_hidden: bool,
+        mut edits: Vec<Edit>,
+    ) -> String {
+        self.collect_attr_edits(header, doc_hidden, &mut edits);
+        self.collect_attr_edits(item, doc_hidden, &mut edits);
+        edits.extend(self.cfg_edits.iter().cloned());
+        let (header_range, indent) = self.source.expand_to_line_start(header_range);
+        let (item_range, _) = self.source.expand_to_line_start(item_range);
+        let text = format!(
+            "{}\n{}\n{}}}",
+            self.source.slice_with_edits(header_range, &edits),
+            self.source.slice_with_edits(item_range, &edits).trim_end(),
+            indent
+        );
+        let mut content = dedent(&text, indent);
+        content.push('\n');
+        content
+    }
+
+    fn collect_attr_edits(&self, tokens: TokenStream, doc_hidden: bool, edits: &mut Vec<Edit>) {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let mut i = 0;
+        while i < tokens.len() {
+            match tokens[i] {
+                TokenTree::Punct(ref punct) if punct.as_char() == '#' => {
+                    let is_inner = matches!(tokens.get(i + 1), Some(TokenTree::Punct(ref p)) if p.as_char() == '!');
+                    let j = if is_inner { i + 2 } else { i + 1 };
+                    if let Some(TokenTree::Group(ref g)) = tokens.get(j) {
+                        if g.delimiter() == Delimiter::Bracket
+                            && (is_snippet_attr_group(g)
+                                || doc_hidden && next_token_is_doc(&tokens[j]))
+                        {
+                            let range = self.source.range(punct.span()).start
+                                ..self.source.range(g.span()).end;
+                            edits.push(Edit::remove(self.source.removal_range(range)));
+                            i = j + 1;
+                            continue;
+                        }
+                    }
+                }
+                TokenTree::Group(ref g) => self.collect_attr_edits(g.stream(), doc_hidden, edits),
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+}
+
+// Whether the content of `#[...]` is a snippet attribute.
+fn is_snippet_attr_group(group: &Group) -> bool {
+    let path = group
+        .stream()
+        .into_iter()
+        .take_while(|t| match t {
+            TokenTree::Group(_) => false,
+            TokenTree::Punct(ref p) => p.as_char() == ':',
+            _ => true,
+        })
+        .collect::<TokenStream>();
+    is_snippet_path(path.to_string().as_str())
+}
+
+// `use` declaration to make `item` in `module_path` available at the top level.
+// The visibility follows the item's one. Private items are not re-exported.
+fn reexport(module_path: &[String], item: &Item) -> Option<String> {
+    if module_path.is_empty() {
+        return None;
+    }
+    let (vis, ident) = match item {
+        Item::Const(ref item) => (&item.vis, &item.ident),
+        Item::Enum(ref item) => (&item.vis, &item.ident),
+        Item::Fn(ref item) => (&item.vis, &item.sig.ident),
+        Item::Mod(ref item) => (&item.vis, &item.ident),
+        Item::Static(ref item) => (&item.vis, &item.ident),
+        Item::Struct(ref item) => (&item.vis, &item.ident),
+        Item::Trait(ref item) => (&item.vis, &item.ident),
+        Item::Type(ref item) => (&item.vis, &item.ident),
+        Item::Union(ref item) => (&item.vis, &item.ident),
+        _ => return None,
+    };
+    let vis = match vis {
+        Visibility::Inherited => return None,
+        Visibility::Public(_) => "pub",
+        _ => "pub(crate)",
+    };
+    Some(format!(
+        "{} use {}::{};",
+        vis,
+        module_path.join("::"),
+        ident
+    ))
+}
+
 // Get snippet names and snippet code (not formatted)
-fn get_snippet_from_item(mut item: Item) -> Option<Snippet> {
+// `scope_uses` are `use` declarations of the module where `item` is defined.
+fn get_snippet_from_item(
+    ctx: &Context,
+    module_path: &[String],
+    scope_uses: &[String],
+    item: Item,
+) -> Option<Snippet> {
     let default_name = get_default_snippet_name(&item);
-    let snip_attrs = get_attrs(&item).and_then(|attrs| parse_attrs(attrs.as_slice(), default_name));
+    let item_attrs = get_attrs(&item).cloned().unwrap_or_default();
+    let snip_attrs = parse_attrs(ctx, &item_attrs, default_name);
+    let item_span = item.span();
 
     snip_attrs.map(|attrs| {
-        remove_snippet_attr(&mut item);
         let doc_hidden = attrs.doc_hidden;
+        let kept = match item {
+            Item::Mod(ref item_mod) => vec![vec![item_mod.ident.to_string()]],
+            _ => Vec::new(),
+        };
+        let content_filter = ctx.content_filter(&attrs);
+        let render = |wrapped: bool| {
+            let rewriter = ctx.path_rewriter(wrapped, module_path, kept.clone());
+            if ctx.is_verbatim(&attrs) {
+                let mut edits = rewriter.edits(&ctx.source, |v| v.visit_item(&item));
+                edits.extend(content_filter.edits(&ctx.source, |v| visit::visit_item(v, &item)));
+                ctx.verbatim(
+                    item.to_token_stream(),
+                    ctx.source.range(item.span()),
+                    doc_hidden,
+                    edits,
+                )
+            } else {
+                let mut item = item.clone();
+                remove_snippet_attr(&mut item);
+                content_filter.rewrite(|v| visit_mut::visit_item_mut(v, &mut item));
+                substitute(
+                    &attrs.subst,
+                    &mut item,
+                    |v, item| v.visit_item(item),
+                    |v, item| v.visit_item_mut(item),
+                );
+                rewriter.rewrite(|v| v.visit_item_mut(&mut item));
+                stringify_tokens(item.into_token_stream(), doc_hidden)
+            }
+        };
+        let content = render(attrs.wrap_mod);
+        let wrapped = if attrs.wrap_mod {
+            content.clone()
+        } else {
+            render(true)
+        };
+        Snippet {
+            content,
+            location: ctx.snippet_location(&item_attrs),
+            lines: ctx.lines(item_span),
+            module_path: module_path.to_vec(),
+            wrapped: Some(wrapped),
+            reexports: reexport(module_path, &item).into_iter().collect(),
+            scope_uses: scope_uses.to_vec(),
+            attrs,
+        }
+    })
+}
+
+// Get snippet from an associated item.
+// If `wrap_impl` is specified, the item is emitted inside its enclosing `impl` block.
+fn get_snippet_from_impl_item(
+    ctx: &Context,
+    module_path: &[String],
+    scope_uses: &[String],
+    item_impl: &ItemImpl,
+    item: ImplItem,
+) -> Option<Snippet> {
+    let default_name = get_default_snippet_name_impl_item(&item);
+    let item_attrs = get_impl_item_attrs(&item).cloned().unwrap_or_default();
+    let snip_attrs = parse_attrs(ctx, &item_attrs, default_name);
+    let item_span = item.span();
+
+    snip_attrs.map(|mut attrs| {
+        // A bare associated item can't be in a module.
+        attrs.wrap_mod &= attrs.wrap_impl;
+        let doc_hidden = attrs.doc_hidden;
+        let content_filter = ctx.content_filter(&attrs);
+        let render = |wrapped: bool| {
+            let rewriter = ctx.path_rewriter(wrapped, module_path, Vec::new());
+            if ctx.is_verbatim(&attrs) {
+                let item_range = ctx.source.range(item.span());
+                let mut edits =
+                    content_filter.edits(&ctx.source, |v| visit::visit_impl_item(v, &item));
+                if attrs.wrap_impl {
+                    edits.extend(rewriter.edits(&ctx.source, |v| v.visit_item_impl(item_impl)));
+                    let mut header = item_impl.clone();
+                    header.items.clear();
+                    let header_range = ctx.source.range(item_impl.span()).start
+                        ..ctx.source.range(item_impl.brace_token.span).start + 1;
+                    ctx.verbatim_wrapped(
+                        header.to_token_stream(),
+                        header_range,
+                        item.to_token_stream(),
+                        item_range,
+                        doc_hidden,
+                        edits,
+                    )
+                } else {
+                    edits.extend(rewriter.edits(&ctx.source, |v| v.visit_impl_item(&item)));
+                    ctx.verbatim(item.to_token_stream(), item_range, doc_hidden, edits)
+                }
+            } else {
+                let mut item = item.clone();
+                remove_snippet_attr_impl_item(&mut item);
+                content_filter.rewrite(|v| visit_mut::visit_impl_item_mut(v, &mut item));
+                let tokens = if attrs.wrap_impl {
+                    let mut item_impl = item_impl.clone();
+                    item_impl.items = vec![item];
+                    let mut wrapper = Item::Impl(item_impl);
+                    remove_snippet_attr(&mut wrapper);
+                    substitute(
+                        &attrs.subst,
+                        &mut wrapper,
+                        |v, item| v.visit_item(item),
+                        |v, item| v.visit_item_mut(item),
+                    );
+                    rewriter.rewrite(|v| v.visit_item_mut(&mut wrapper));
+                    wrapper.into_token_stream()
+                } else {
+                    substitute(
+                        &attrs.subst,
+                        &mut item,
+                        |v, item| v.visit_impl_item(item),
+                        |v, item| v.visit_impl_item_mut(item),
+                    );
+                    rewriter.rewrite(|v| v.visit_impl_item_mut(&mut item));
+                    item.into_token_stream()
+                };
+                stringify_tokens(tokens, doc_hidden)
+            }
+        };
+        let content = render(attrs.wrap_mod);
+        let wrapped = match (attrs.wrap_mod, attrs.wrap_impl) {
+            (true, _) => Some(content.clone()),
+            (false, true) => Some(render(true)),
+            (false, false) => None,
+        };
+        Snippet {
+            content,
+            location: ctx.snippet_location(&item_attrs),
+            lines: ctx.lines(item_span),
+            module_path: module_path.to_vec(),
+            wrapped,
+            reexports: Vec::new(),
+            scope_uses: scope_uses.to_vec(),
+            attrs,
+        }
+    })
+}
+
+// Get snippet from a trait item.
+// If `wrap_impl` is specified, the item is emitted inside its enclosing `trait` block.
+fn get_snippet_from_trait_item(
+    ctx: &Context,
+    module_path: &[String],
+    scope_uses: &[String],
+    item_trait: &ItemTrait,
+    item: TraitItem,
+) -> Option<Snippet> {
+    let default_name = get_default_snippet_name_trait_item(&item);
+    let item_attrs = get_trait_item_attrs(&item).cloned().unwrap_or_default();
+    let snip_attrs = parse_attrs(ctx, &item_attrs, default_name);
+    let item_span = item.span();
+
+    snip_attrs.map(|mut attrs| {
+        // A bare associated item can't be in a module.
+        attrs.wrap_mod &= attrs.wrap_impl;
+        let doc_hidden = attrs.doc_hidden;
+        let content_filter = ctx.content_filter(&attrs);
+        let render = |wrapped: bool| {
+            let rewriter = ctx.path_rewriter(wrapped, module_path, Vec::new());
+            if ctx.is_verbatim(&attrs) {
+                let item_range = ctx.source.range(item.span());
+                let mut edits =
+                    content_filter.edits(&ctx.source, |v| visit::visit_trait_item(v, &item));
+                if attrs.wrap_impl {
+                    edits.extend(rewriter.edits(&ctx.source, |v| v.visit_item_trait(item_trait)));
+                    let mut header = item_trait.clone();
+                    header.items.clear();
+                    let header_range = ctx.source.range(item_trait.span()).start
+                        ..ctx.source.range(item_trait.brace_token.span).start + 1;
+                    ctx.verbatim_wrapped(
+                        header.to_token_stream(),
+                        header_range,
+                        item.to_token_stream(),
+                        item_range,
+                        doc_hidden,
+                        edits,
+                    )
+                } else {
+                    edits.extend(rewriter.edits(&ctx.source, |v| v.visit_trait_item(&item)));
+                    ctx.verbatim(item.to_token_stream(), item_range, doc_hidden, edits)
+                }
+            } else {
+                let mut item = item.clone();
+                remove_snippet_attr_trait_item(&mut item);
+                content_filter.rewrite(|v| visit_mut::visit_trait_item_mut(v, &mut item));
+                let tokens = if attrs.wrap_impl {
+                    let mut item_trait = item_trait.clone();
+                    item_trait.items = vec![item];
+                    let mut wrapper = Item::Trait(item_trait);
+                    remove_snippet_attr(&mut wrapper);
+                    substitute(
+                        &attrs.subst,
+                        &mut wrapper,
+                        |v, item| v.visit_item(item),
+                        |v, item| v.visit_item_mut(item),
+                    );
+                    rewriter.rewrite(|v| v.visit_item_mut(&mut wrapper));
+                    wrapper.into_token_stream()
+                } else {
+                    substitute(
+                        &attrs.subst,
+                        &mut item,
+                        |v, item| v.visit_trait_item(item),
+                        |v, item| v.visit_trait_item_mut(item),
+                    );
+                    rewriter.rewrite(|v| v.visit_trait_item_mut(&mut item));
+                    item.into_token_stream()
+                };
+                stringify_tokens(tokens, doc_hidden)
+            }
+        };
+        let content = render(attrs.wrap_mod);
+        let wrapped = match (attrs.wrap_mod, attrs.wrap_impl) {
+            (true, _) => Some(content.clone()),
+            (false, true) => Some(render(true)),
+            (false, false) => None,
+        };
         Snippet {
+            content,
+            location: ctx.snippet_location(&item_attrs),
+            lines: ctx.lines(item_span),
+            module_path: module_path.to_vec(),
+            wrapped,
+            reexports: Vec::new(),
+            scope_uses: scope_uses.to_vec(),
             attrs,
-            content: stringify_tokens(item.into_token_stream(), doc_hidden),
         }
     })
 }
 
-fn get_snippet_from_item_recursive(item: Item) -> Vec<Snippet> {
+// `use` declarations among `items` as text
+fn use_items(items: &[Item]) -> Vec<String> {
+    items
+        .iter()
+        .filter(|item| matches!(item, Item::Use(_)))
+        .map(|item| item.to_token_stream().to_string())
+        .collect()
+}
+
+// `module_path` is the path of the module where `item` is defined and `scope_uses` are its `use` declarations.
+fn get_snippet_from_item_recursive(
+    ctx: &Context,
+    module_path: &[String],
+    scope_uses: &[String],
+    item: Item,
+) -> Vec<Snippet> {
     let mut res = Vec::new();
 
-    if let Some(pair) = get_snippet_from_item(item.clone()) {
+    if let Some(pair) = get_snippet_from_item(ctx, module_path, scope_uses, item.clone()) {
         res.push(pair);
     }
 
-    if let Item::Mod(mod_item) = item {
-        res.extend(
-            mod_item
-                .content
-                .into_iter()
-                .flat_map(|(_, items)| items.into_iter().flat_map(get_snippet_from_item_recursive)),
-        );
+    match item {
+        Item::Mod(mod_item) => {
+            let mut child_path = module_path.to_vec();
+            child_path.push(mod_item.ident.to_string());
+            res.extend(mod_item.content.into_iter().flat_map(|(_, items)| {
+                let child_uses = use_items(&items);
+                items
+                    .into_iter()
+                    .flat_map(|item| {
+                        get_snippet_from_item_recursive(ctx, &child_path, &child_uses, item)
+                    })
+                    .collect::<Vec<_>>()
+            }));
+        }
+        Item::Impl(ref impl_item) => {
+            res.extend(impl_item.items.iter().cloned().filter_map(|item| {
+                get_snippet_from_impl_item(ctx, module_path, scope_uses, impl_item, item)
+            }));
+        }
+        Item::Trait(ref trait_item) => {
+            res.extend(trait_item.items.iter().cloned().filter_map(|item| {
+                get_snippet_from_trait_item(ctx, module_path, scope_uses, trait_item, item)
+            }));
+        }
+        _ => (),
     }
 
     res
 }
 
-fn get_snippet_from_file(file: File) -> Vec<Snippet> {
+fn get_snippet_from_file(ctx: &Context, file: File) -> Vec<Snippet> {
     let mut res = Vec::new();
 
     // whole code is snippet
-    if let Some(attrs) = parse_attrs(&file.attrs, None) {
-        let mut file = file.clone();
-        file.attrs.retain(|attr| {
-            attr.parse_meta()
-                .map(|m| !is_snippet_path(m.path().to_token_stream().to_string().as_str()))
-                .unwrap_or(true)
-        });
-        file.items.iter_mut().for_each(|item| {
-            remove_snippet_attr(item);
-        });
+    if let Some(attrs) = parse_attrs(ctx, &file.attrs, None) {
         let doc_hidden = attrs.doc_hidden;
+        let content_filter = ctx.content_filter(&attrs);
+        // Private items are not imported by a glob.
+        let reexports = if ctx.module_path.is_empty() {
+            Vec::new()
+        } else {
+            vec![format!("pub use {}::*;", ctx.module_path.join("::"))]
+        };
+        let render = |wrapped: bool| {
+            let rewriter = ctx.path_rewriter(wrapped, ctx.module_path, vec![Vec::new()]);
+            if ctx.is_verbatim(&attrs) {
+                let mut edits = rewriter.edits(&ctx.source, |v| v.visit_file(&file));
+                edits.extend(content_filter.edits(&ctx.source, |v| v.visit_file(&file)));
+                ctx.verbatim(
+                    file.to_token_stream(),
+                    0..ctx.source.text().len(),
+                    doc_hidden,
+                    edits,
+                )
+            } else {
+                let mut file = file.clone();
+                file.attrs.retain(|attr| {
+                    attr.parse_meta()
+                        .map(|m| !is_snippet_path(m.path().to_token_stream().to_string().as_str()))
+                        .unwrap_or(true)
+                });
+                file.items.iter_mut().for_each(remove_snippet_attr);
+                content_filter.rewrite(|v| v.visit_file_mut(&mut file));
+                substitute(
+                    &attrs.subst,
+                    &mut file,
+                    |v, file| v.visit_file(file),
+                    |v, file| v.visit_file_mut(file),
+                );
+                rewriter.rewrite(|v| v.visit_file_mut(&mut file));
+                stringify_tokens(file.into_token_stream(), doc_hidden)
+            }
+        };
+        let content = render(attrs.wrap_mod);
+        let wrapped = if attrs.wrap_mod {
+            content.clone()
+        } else {
+            render(true)
+        };
         res.push(Snippet {
+            content,
+            location: ctx.snippet_location(&file.attrs),
+            lines: Some((1, ctx.source.text().lines().count())),
+            module_path: ctx.module_path.to_vec(),
+            wrapped: Some(wrapped),
+            reexports,
+            // The declarations are a part of the content.
+            scope_uses: Vec::new(),
             attrs,
-            content: stringify_tokens(file.into_token_stream(), doc_hidden),
         })
     }
 
+    let mut finder = BlockFinder {
+        ctx,
+        module_path: ctx.module_path.to_vec(),
+        snippets: Vec::new(),
+    };
+    finder.visit_file(&file);
+    let blocks = finder.snippets;
+
+    let scope_uses = use_items(&file.items);
     res.extend(
-        file.items
-            .into_iter()
-            .flat_map(get_snippet_from_item_recursive),
+        file.items.into_iter().flat_map(|item| {
+            get_snippet_from_item_recursive(ctx, ctx.module_path, &scope_uses, item)
+        }),
     );
+    res.extend(blocks);
 
     res
 }
 
-pub fn parse_snippet(src: &str) -> Result<Vec<Snippet>, syn::parse::Error> {
-    parse_file(src).map(get_snippet_from_file)
+// Finds `snippet_block! { name = "...", { ... } }` anywhere in a file.
+struct BlockFinder<'a, 'b> {
+    ctx: &'b Context<'a>,
+    module_path: Vec<String>,
+    snippets: Vec<Snippet>,
+}
+
+impl<'a, 'b, 'ast> Visit<'ast> for BlockFinder<'a, 'b> {
+    fn visit_item_mod(&mut self, item_mod: &'ast ItemMod) {
+        self.module_path.push(item_mod.ident.to_string());
+        visit::visit_item_mod(self, item_mod);
+        self.module_path.pop();
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        if is_snippet_block_path(mac.path.to_token_stream().to_string().as_str()) {
+            if let Some(snippet) = get_snippet_from_block(self.ctx, &self.module_path, mac) {
+                self.snippets.push(snippet);
+            }
+        }
+        visit::visit_macro(self, mac);
+    }
+}
+
+// Get a snippet from the statements in `snippet_block!`.
+// Arguments are the same as `#[snippet(...)]` except that a name is required.
+fn get_snippet_from_block(ctx: &Context, module_path: &[String], mac: &Macro) -> Option<Snippet> {
+    let mut tokens = mac.tokens.clone().into_iter().collect::<Vec<_>>();
+    let group = match tokens.pop() {
+        Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Brace => g.clone(),
+        _ => {
+            ctx.report(
+                Diagnostic::error("expected `snippet_block! { name = \"...\", { ... } }`"),
+                mac.span(),
+            );
+            return None;
+        }
+    };
+    if matches!(tokens.last(), Some(TokenTree::Punct(ref p)) if p.as_char() == ',') {
+        tokens.pop();
+    }
+
+    let args = tokens.into_iter().collect::<TokenStream>();
+    let attr: Attribute = parse_quote!(#[snippet(#args)]);
+    let location = Some(ctx.location(mac.path.span()));
+    let mut attrs = match parse_attrs(ctx, &[attr], None) {
+        Some(attrs) => attrs,
+        None => {
+            ctx.report(
+                Diagnostic::error("`snippet_block!` requires a snippet name"),
+                mac.path.span(),
+            );
+            return None;
+        }
+    };
+    // Statements can't be in a module.
+    attrs.wrap_mod = false;
+    let block: Block = match syn::parse2(TokenTree::Group(group.clone()).into()) {
+        Ok(block) => block,
+        Err(e) => {
+            ctx.report(Diagnostic::error(e.to_string()), e.span());
+            return None;
+        }
+    };
+
+    let rewriter = ctx.path_rewriter(false, module_path, Vec::new());
+    let content_filter = ctx.content_filter(&attrs);
+    let content = if ctx.is_verbatim(&attrs) {
+        // Inside the braces
+        let range = ctx.source.range(group.span());
+        let range = range.start + 1..range.end - 1;
+        let mut edits = rewriter.edits(&ctx.source, |v| v.visit_block(&block));
+        edits.extend(content_filter.edits(&ctx.source, |v| v.visit_block(&block)));
+        edits.extend(ctx.cfg_edits.iter().cloned());
+        let text = ctx.source.slice_with_edits(range, &edits);
+        let text = text.trim_start_matches(['\r', '\n']);
+        let indent = &text[..text.len() - text.trim_start().len()];
+        format!("{}\n", dedent(text, indent).trim_end())
+    } else {
+        let mut block = block;
+        content_filter.rewrite(|v| v.visit_block_mut(&mut block));
+        substitute(
+            &attrs.subst,
+            &mut block,
+            |v, block| v.visit_block(block),
+            |v, block| v.visit_block_mut(block),
+        );
+        rewriter.rewrite(|v| v.visit_block_mut(&mut block));
+        let tokens = block
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_token_stream())
+            .collect::<TokenStream>();
+        stringify_tokens(tokens, attrs.doc_hidden)
+    };
+    Some(Snippet {
+        attrs,
+        content,
+        location,
+        lines: ctx.lines(mac.span()),
+        module_path: module_path.to_vec(),
+        wrapped: None,
+        reexports: Vec::new(),
+        scope_uses: Vec::new(),
+    })
+}
+
+// Extract snippets from `src`. `file` is where `src` is read from, which is used in diagnostics.
+pub fn parse_snippet(
+    src: &str,
+    file: Option<&ModuleFile>,
+    options: &ParseOptions,
+) -> (Vec<Snippet>, Vec<Diagnostic>) {
+    let mut ctx = Context {
+        source: Source::new(src),
+        file: file.map(|f| f.path.as_path()),
+        module_path: file.map(|f| f.module_path.as_slice()).unwrap_or(&[]),
+        options,
+        diagnostics: RefCell::new(Vec::new()),
+        cfg_edits: Vec::new(),
+    };
+    let mut snippets = match parse_file(src) {
+        Ok(mut parsed) => match options.cfg {
+            Some(ref cfg) => match cfg.strip_file(&ctx.source, &mut parsed) {
+                Some(edits) => {
+                    ctx.cfg_edits = edits;
+                    get_snippet_from_file(&ctx, parsed)
+                }
+                // The whole file is disabled.
+                None => Vec::new(),
+            },
+            None => get_snippet_from_file(&ctx, parsed),
+        },
+        Err(e) => {
+            ctx.report(Diagnostic::error(e.to_string()), e.span());
+            Vec::new()
+        }
+    };
+    for snip in &mut snippets {
+        if !snip.attrs.placeholders.is_empty() {
+            let names = &snip.attrs.placeholders;
+            snip.content = placeholder::mark(&snip.content, names);
+            // `wrapped` is used instead of `content` if the snippet is combined with `wrap_mod`.
+            snip.wrapped = snip.wrapped.as_ref().map(|w| placeholder::mark(w, names));
+        }
+    }
+    (snippets, ctx.diagnostics.into_inner())
 }
 
 #[cfg(test)]
 mod test {
-    use super::{parse_snippet, unescape};
+    use super::{parse_snippet, stringify_tokens, unescape, ParseOptions};
+    use crate::cfg::Cfg;
+    use crate::modtree::ModuleFile;
     use crate::snippet::process_snippets;
     use crate::writer::format_src;
     use quote::quote;
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::path::PathBuf;
 
     fn snippets(src: &str) -> BTreeMap<String, String> {
-        let snips = parse_snippet(src).unwrap();
-        process_snippets(&snips)
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        process_snippets(&snips, &BTreeMap::new())
+    }
+
+    fn verbatim_snippets(src: &str) -> BTreeMap<String, String> {
+        let options = ParseOptions {
+            verbatim: true,
+            ..ParseOptions::default()
+        };
+        let snips = parse_snippet(src, None, &options).0;
+        process_snippets(&snips, &BTreeMap::new())
     }
 
     #[test]
@@ -784,7 +1679,7 @@ mod test {
                 .to_string()
             )
         );
-        // Original order of "uses" are not saved.
+        // Original order of "uses" is kept.
         assert_eq!(
             format_src(snip["baz"].as_str()).unwrap(),
             format_src("fn foo() {} fn bar() {} fn baz() {}").unwrap()
@@ -812,6 +1707,35 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_deep_dependency_order() {
+        let src = r#"
+            #[snippet(include = "chmax")]
+            fn foo() { chmax!(); }
+
+            #[snippet(include = "bar, chmax")]
+            fn baz() { foo(); chmax!(); }
+
+            #[snippet(include = "foo")]
+            fn bar() { foo(); }
+
+            #[snippet("chmax")]
+            macro_rules! chmax {
+                () => {};
+            }
+        "#;
+
+        let snip = snippets(src);
+        // Every dependency precedes its dependents.
+        assert_eq!(
+            format_src(snip["baz"].as_str()).unwrap(),
+            format_src(
+                "macro_rules! chmax { () => {}; } fn foo() { chmax!(); } fn bar() { foo(); } fn baz() { foo(); chmax!(); }"
+            )
+            .unwrap()
+        );
+    }
+
     #[test]
     fn test_missing_dependency() {
         let src = r#"
@@ -868,7 +1792,7 @@ mod test {
         let snip = snippets(&src);
         assert_eq!(
             format_src(snip["bar"].as_str()).unwrap(),
-            format_src("use std::io::{self,Read};\nuse std::str::FromStr;\nfn bar() {}").unwrap()
+            format_src("use std::{io::{self, Read}, str::FromStr};\nfn bar() {}").unwrap()
         );
 
         let src = r#"
@@ -880,7 +1804,7 @@ use std::str::FromStr;")]
         let snip = snippets(&src);
         assert_eq!(
             format_src(snip["bar"].as_str()).unwrap(),
-            format_src("use std::io::{self,Read};\nuse std::str::FromStr;\nfn bar() {}").unwrap()
+            format_src("use std::{io::{self, Read}, str::FromStr};\nfn bar() {}").unwrap()
         );
     }
 
@@ -898,8 +1822,30 @@ use std::str::FromStr;")]
             format_src(snip["bar"].as_str()).unwrap(),
             format_src(
                 &quote!(
-                    use std::sync;
-                    use std::io;
+                    use std::{io, sync};
+                    fn foo() {}
+                    fn bar() {}
+                )
+                .to_string()
+            )
+            .unwrap()
+        );
+
+        // Identical imports are emitted once. The order of other lines is kept.
+        let src = r#"
+            #[snippet(prefix = "use std::io::Read;\nconst MOD: u64 = 7;")]
+            fn foo() {}
+            #[snippet(prefix = "use std::io::Read;", include = "foo")]
+            fn bar() {}
+        "#;
+
+        let snip = snippets(&src);
+        assert_eq!(
+            format_src(snip["bar"].as_str()).unwrap(),
+            format_src(
+                &quote!(
+                    use std::io::Read;
+                    const MOD: u64 = 7;
                     fn foo() {}
                     fn bar() {}
                 )
@@ -1302,4 +2248,726 @@ fn foo() {}
             format_src("use std::collections::HashMap;\nfn foo() {}").unwrap(),
         );
     }
+
+    #[test]
+    fn test_impl_item() {
+        let src = r#"
+            struct Foo(u32);
+
+            impl Foo {
+                #[snippet]
+                fn get(&self) -> u32 {
+                    self.0
+                }
+
+                #[snippet(name = "foo_zero")]
+                const ZERO: u32 = 0;
+
+                fn not_snippet(&self) {}
+            }
+        "#;
+
+        let snip = snippets(&src);
+        assert_eq!(
+            format_src(snip["get"].as_str()).unwrap(),
+            format_src("fn get(&self) -> u32 { self.0 }").unwrap(),
+        );
+        assert_eq!(
+            format_src(snip["foo_zero"].as_str()).unwrap(),
+            format_src("const ZERO: u32 = 0;").unwrap(),
+        );
+        assert_eq!(snip.get("not_snippet"), None);
+    }
+
+    #[test]
+    fn test_impl_item_wrap_impl() {
+        let src = r#"
+            struct Foo<T>(T);
+
+            /// impl doc
+            impl<T: Copy> Foo<T> where T: Default {
+                #[snippet(wrap_impl)]
+                fn get(&self) -> T {
+                    self.0
+                }
+
+                fn other(&self) {}
+            }
+        "#;
+
+        let snip = snippets(&src);
+        assert_eq!(
+            format_src(snip["get"].as_str()).unwrap(),
+            format_src(
+                "/// impl doc\nimpl<T: Copy> Foo<T> where T: Default { fn get(&self) -> T { self.0 } }"
+            )
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_trait_item() {
+        let src = r#"
+            trait Monoid {
+                #[snippet]
+                fn id() -> Self;
+
+                #[snippet(name = "op", wrap_impl)]
+                fn op(&self, other: &Self) -> Self;
+            }
+        "#;
+
+        let snip = snippets(&src);
+        assert_eq!(
+            format_src(snip["id"].as_str()).unwrap(),
+            format_src("fn id() -> Self;").unwrap(),
+        );
+        assert_eq!(
+            format_src(snip["op"].as_str()).unwrap(),
+            format_src("trait Monoid { fn op(&self, other: &Self) -> Self; }").unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_impl_item_in_snippet_impl() {
+        let src = r#"
+            #[snippet("foo_impl")]
+            impl Foo {
+                #[snippet]
+                fn get(&self) -> u32 {
+                    self.0
+                }
+            }
+        "#;
+
+        let snip = snippets(&src);
+        // #[snippet] on the method should be removed.
+        assert_eq!(
+            format_src(snip["foo_impl"].as_str()).unwrap(),
+            format_src("impl Foo { fn get(&self) -> u32 { self.0 } }").unwrap(),
+        );
+        assert_eq!(
+            format_src(snip["get"].as_str()).unwrap(),
+            format_src("fn get(&self) -> u32 { self.0 }").unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_verbatim() {
+        let src = r#"
+// Not a part of the snippet
+/// gcd
+#[snippet]
+#[snippet("mymath")]
+pub fn gcd(a: u64, b: u64) -> u64 {
+    // O(log n)
+    if b == 0 { a } else { gcd(b, a % b) } /* tail */
+}
+"#;
+
+        let snip = verbatim_snippets(src);
+        assert_eq!(
+            snip["gcd"],
+            "/// gcd\npub fn gcd(a: u64, b: u64) -> u64 {\n    // O(log n)\n    if b == 0 { a } else { gcd(b, a % b) } /* tail */\n}\n"
+        );
+        assert_eq!(snip["gcd"], snip["mymath"]);
+    }
+
+    #[test]
+    fn test_verbatim_attribute() {
+        let src = r#"
+#[snippet(verbatim, include = "bar")]
+fn foo() {
+    // comment
+}
+
+#[snippet(name = "bar", doc_hidden, verbatim, prefix = "use std::io;")]
+/// doc
+fn bar() {}
+"#;
+
+        let snip = snippets(src);
+        assert_eq!(
+            snip["foo"],
+            "use std::io;\nfn bar() {}\nfn foo() {\n    // comment\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_verbatim_nested() {
+        let src = r#"
+#[snippet]
+mod foo {
+    #[snippet] // hoge
+    pub fn hoge() {
+        // comment
+    }
+
+    impl Foo {
+        /// doc
+        #[snippet(wrap_impl)]
+        fn bar(&self) {
+            // bar
+        }
+
+        #[snippet(wrap_impl)]
+        fn baz(&self) {}
+    }
+}
+"#;
+
+        let snip = verbatim_snippets(src);
+        assert_eq!(
+            snip["foo"],
+            "mod foo {\n    // hoge\n    pub fn hoge() {\n        // comment\n    }\n\n    impl Foo {\n        /// doc\n        fn bar(&self) {\n            // bar\n        }\n\n        fn baz(&self) {}\n    }\n}\n"
+        );
+        assert_eq!(
+            snip["hoge"],
+            "// hoge\npub fn hoge() {\n    // comment\n}\n"
+        );
+        assert_eq!(
+            snip["bar"],
+            "impl Foo {\n    /// doc\n    fn bar(&self) {\n        // bar\n    }\n}\n"
+        );
+        assert_eq!(snip["baz"], "impl Foo {\n    fn baz(&self) {}\n}\n");
+    }
+
+    #[test]
+    fn test_verbatim_file() {
+        let src = r#"#![snippet("file")]
+// comment
+fn foo() {}
+"#;
+
+        let snip = verbatim_snippets(src);
+        assert_eq!(snip["file"], "// comment\nfn foo() {}\n");
+    }
+
+    #[test]
+    fn test_attribute_diagnostics() {
+        let src = r#"
+#[snippet(name = "foo", bar, prefix = 1)]
+fn foo() {}
+
+#[snippet(include = foo)]
+fn baz() {}
+
+#[snippet("ok", doc_hidden = "yes")]
+fn ok() {}
+"#;
+
+        let (snips, diags) = parse_snippet(src, None, &ParseOptions::default());
+        // Attributes which can't be parsed are ignored.
+        assert_eq!(snips.len(), 2);
+        assert_eq!(
+            diags
+                .iter()
+                .map(|d| (
+                    d.message.as_str(),
+                    d.location.as_ref().map(|l| (l.line, l.column))
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "unknown snippet attribute `bar`. Expected one of `name`, `include`, `prefix`, `placeholder`, `subst`, `doc_hidden`, `wrap_impl`, `verbatim`, `wrap_mod`, `keep_tests`, `skip`",
+                    Some((2, 24))
+                ),
+                ("value of `prefix` must be a string literal", Some((2, 29))),
+                (
+                    "malformed snippet attribute: expected literal",
+                    Some((5, 20))
+                ),
+                ("`doc_hidden` doesn't take a value", Some((8, 16))),
+            ]
+        );
+        assert!(diags.iter().all(|d| d.is_error()));
+
+        let (snips, diags) = parse_snippet("fn foo(", None, &ParseOptions::default());
+        assert!(snips.is_empty());
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].is_error());
+    }
+
+    #[test]
+    fn test_module_path() {
+        let src = r#"
+#[snippet]
+fn root() {}
+
+mod math {
+    #[snippet]
+    mod modint {
+        #[snippet]
+        struct ModInt(u64);
+
+        impl ModInt {
+            #[snippet]
+            fn new() -> ModInt { ModInt(0) }
+        }
+    }
+}
+"#;
+
+        let file = ModuleFile {
+            path: PathBuf::from("src/algo.rs"),
+            module_path: vec!["algo".to_string()],
+        };
+        let (snips, _) = parse_snippet(src, Some(&file), &ParseOptions::default());
+        let module_paths = snips
+            .iter()
+            .map(|s| {
+                (
+                    s.attrs.names.iter().next().unwrap().as_str(),
+                    s.module_path.join("::"),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            module_paths,
+            vec![
+                ("root", "algo".to_string()),
+                ("modint", "algo::math".to_string()),
+                ("ModInt", "algo::math::modint".to_string()),
+                ("new", "algo::math::modint".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths() {
+        let src = r#"
+use crate::math::gcd;
+
+#[snippet]
+pub fn lcm(a: u64, b: u64) -> u64 {
+    a / crate::math::gcd(a, b) * b
+}
+
+#[snippet]
+pub fn sum() -> u64 {
+    use self::lcm;
+    super::math::gcd(1, 2) + ::std::u64::MAX + lcm(2, 3)
+}
+
+#[snippet]
+pub mod inner {
+    use super::lcm;
+    pub fn f() -> u64 {
+        self::g() + crate::algo::inner::g() + lcm(1, 2)
+    }
+    pub fn g() -> u64 {
+        0
+    }
+}
+"#;
+
+        let file = ModuleFile {
+            path: PathBuf::from("src/algo.rs"),
+            module_path: vec!["algo".to_string()],
+        };
+        let modules: BTreeSet<Vec<String>> =
+            [vec![], vec!["math"], vec!["algo"], vec!["algo", "inner"]]
+                .iter()
+                .map(|m| m.iter().map(|s| s.to_string()).collect())
+                .collect();
+        let expected = [
+            (
+                "lcm",
+                "pub fn lcm(a: u64, b: u64) -> u64 { a / gcd(a, b) * b }",
+            ),
+            (
+                "sum",
+                "pub fn sum() -> u64 { gcd(1, 2) + ::std::u64::MAX + lcm(2, 3) }",
+            ),
+            (
+                "inner",
+                r"pub mod inner {
+                    use super::lcm;
+                    pub fn f() -> u64 { g() + g() + lcm(1, 2) }
+                    pub fn g() -> u64 { 0 }
+                }",
+            ),
+        ];
+        for &verbatim in &[false, true] {
+            let options = ParseOptions {
+                verbatim,
+                modules: modules.clone(),
+                ..ParseOptions::default()
+            };
+            let snips = parse_snippet(src, Some(&file), &options).0;
+            let snips = process_snippets(&snips, &BTreeMap::new());
+            for (name, content) in expected.iter() {
+                assert_eq!(
+                    format_src(&snips[*name]),
+                    format_src(content),
+                    "{} (verbatim: {})",
+                    name,
+                    verbatim
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrap_mod() {
+        let src = r#"
+#[snippet(wrap_mod)]
+pub struct Node(u64);
+
+#[snippet(wrap_mod)]
+fn helper(x: u64) -> u64 {
+    x
+}
+
+#[snippet(wrap_mod, include = "Node, helper, depth")]
+pub fn pow(n: &Node) -> u64 {
+    crate::algo::helper(n.0) + self::tree::depth()
+}
+
+pub mod tree {
+    #[snippet(wrap_mod)]
+    pub(crate) fn depth() -> u64 {
+        0
+    }
+}
+"#;
+
+        let file = ModuleFile {
+            path: PathBuf::from("src/algo.rs"),
+            module_path: vec!["algo".to_string()],
+        };
+        let snips = parse_snippet(src, Some(&file), &ParseOptions::default()).0;
+        let snips = process_snippets(&snips, &BTreeMap::new());
+        assert_eq!(
+            format_src(&snips["pow"]),
+            format_src(
+                r"pub use algo::Node;
+                pub use algo::pow;
+                pub(crate) use algo::tree::depth;
+                pub mod algo {
+                    pub struct Node(u64);
+                    fn helper(x: u64) -> u64 { x }
+                    pub fn pow(n: &Node) -> u64 {
+                        crate::algo::helper(n.0) + self::tree::depth()
+                    }
+                    pub mod tree {
+                        pub(crate) fn depth() -> u64 { 0 }
+                    }
+                }"
+            )
+        );
+
+        // Bare associated items are not wrapped.
+        let src = r#"
+#[snippet]
+fn f() {}
+
+impl S {
+    #[snippet]
+    fn g() {}
+}
+"#;
+        let options = ParseOptions {
+            wrap_mod: true,
+            ..ParseOptions::default()
+        };
+        let snips = parse_snippet(src, Some(&file), &options).0;
+        let snips = process_snippets(&snips, &BTreeMap::new());
+        assert_eq!(
+            format_src(&snips["f"]),
+            format_src("pub mod algo { fn f() {} }")
+        );
+        assert_eq!(format_src(&snips["g"]), format_src("fn g() {}"));
+    }
+
+    #[test]
+    fn test_snippet_block() {
+        let src = r#"
+fn main() {
+    let s = "3";
+    snippet_block! { name = "parse_n", include = "gcd", {
+        // Read n
+        let n: usize = s.parse().unwrap();
+    }}
+    for _ in 0..n {
+        cargo_snippet::snippet_block! { "loop_body", verbatim, {
+            println!("{}", n);
+        }}
+    }
+    snippet_block! { include = "gcd", {} }
+}
+"#;
+
+        let (snips, diags) = parse_snippet(src, None, &ParseOptions::default());
+        assert_eq!(
+            diags.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(),
+            vec!["`snippet_block!` requires a snippet name"]
+        );
+        assert_eq!(snips.len(), 2);
+        assert_eq!(snips[0].attrs.uses, vec!["gcd".to_string()]);
+        assert_eq!(
+            snips[0].content,
+            stringify_tokens(quote!(let n: usize = s.parse().unwrap();), false)
+        );
+        assert_eq!(snips[1].content, "println!(\"{}\", n);\n");
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let src = r#"
+#[snippet(placeholder = "n, dp")]
+fn solve(n: usize) -> u64 {
+    let dp = vec![0; n + 1];
+    dp[__snip_3_m]
+}
+"#;
+
+        for snip in &[snippets(src), verbatim_snippets(src)] {
+            assert_eq!(
+                format_src(&snip["solve"]).unwrap(),
+                format_src(
+                    "fn solve(__snip_1_n: usize) -> u64 { \
+                     let __snip_2_dp = vec![0; __snip_1_n + 1]; __snip_2_dp[__snip_3_m] }"
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_placeholder_wrap_mod() {
+        let src = r#"
+#[snippet(placeholder = "n")]
+pub fn zeros(n: usize) -> Vec<u64> {
+    vec![0; n]
+}
+
+#[snippet(wrap_mod, include = "zeros")]
+pub fn solve() -> Vec<u64> {
+    zeros(3)
+}
+"#;
+
+        let file = ModuleFile {
+            path: PathBuf::from("src/algo.rs"),
+            module_path: vec!["algo".to_string()],
+        };
+        let snips = parse_snippet(src, Some(&file), &ParseOptions::default()).0;
+        let snips = process_snippets(&snips, &BTreeMap::new());
+        // Placeholders are kept in the wrapped content of a dependency.
+        assert_eq!(
+            format_src(&snips["solve"]),
+            format_src(
+                "pub use algo::solve;
+                pub use algo::zeros;
+                pub mod algo {
+                    pub fn zeros(__snip_1_n: usize) -> Vec<u64> { vec![0; __snip_1_n] }
+                    pub fn solve() -> Vec<u64> { zeros(3) }
+                }"
+            )
+        );
+    }
+
+    #[test]
+    fn test_subst() {
+        let src = r#"
+#[snippet]
+const MOD: u64 = 1_000_000_007;
+
+#[snippet(include = "MOD")]
+fn modpow<T: Copy + Into<u64>>(a: T, n: u64) -> u64 {
+    let _ = (a.into(), n);
+    MOD
+}
+
+#[snippet(subst = "T=u32")]
+#[snippet(include = "modpow")]
+fn inv(a: u32) -> u64 {
+    modpow::<u32>(a, MOD - 2)
+}
+"#;
+
+        let options = ParseOptions {
+            subst: vec![("MOD".to_string(), "998_244_353".to_string())],
+            ..ParseOptions::default()
+        };
+        let snips = process_snippets(&parse_snippet(src, None, &options).0, &BTreeMap::new());
+        // Substitution of a dependent is not applied to its dependencies.
+        assert_eq!(
+            format_src(&snips["inv"]).unwrap(),
+            format_src(
+                "const MOD: u64 = 998_244_353; \
+                 fn modpow<T: Copy + Into<u64>>(a: T, n: u64) -> u64 { let _ = (a.into(), n); MOD } \
+                 fn inv(a: u32) -> u64 { modpow::<u32>(a, MOD - 2) }"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subst_verbatim() {
+        let src = r#"
+#[snippet(subst = "T=u64", verbatim)]
+fn zero<T: Default>() -> T {
+    // as is
+    T::default()
+}
+"#;
+        let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+        assert_eq!(
+            snips[0].content,
+            "fn zero<T: Default>() -> T {\n    // as is\n    T::default()\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_cfg() {
+        let src = r#"
+#[cfg(feature = "fast")]
+#[snippet("solve")]
+fn solve() -> u64 {
+    1
+}
+
+#[cfg(not(feature = "fast"))]
+#[snippet("solve")]
+fn solve() -> u64 {
+    2
+}
+
+#[snippet]
+mod io {
+    // Read all
+    pub fn read() -> String {
+        #[cfg_attr(test, allow(unused))]
+        let s = String::new();
+        s
+    }
+
+    #[cfg(test)]
+    mod test {
+        #[test]
+        fn test_read() {}
+    }
+}
+"#;
+        let options = ParseOptions {
+            cfg: Some(Cfg::new(vec!["fast"], vec![])),
+            ..ParseOptions::default()
+        };
+        let snips = process_snippets(&parse_snippet(src, None, &options).0, &BTreeMap::new());
+        assert_eq!(
+            format_src(&snips["solve"]).unwrap(),
+            format_src("fn solve() -> u64 { 1 }").unwrap()
+        );
+        assert_eq!(
+            format_src(&snips["io"]).unwrap(),
+            format_src("mod io { pub fn read() -> String { let s = String::new(); s } }").unwrap()
+        );
+
+        let options = ParseOptions {
+            verbatim: true,
+            ..options
+        };
+        let snips = process_snippets(&parse_snippet(src, None, &options).0, &BTreeMap::new());
+        assert_eq!(snips["solve"], "fn solve() -> u64 {\n    1\n}\n");
+        assert_eq!(
+            snips["io"],
+            "mod io {\n    // Read all\n    pub fn read() -> String {\n        let s = String::new();\n        s\n    }\n\n}\n"
+        );
+
+        // Without options, `cfg` is not evaluated.
+        let snips = snippets(src);
+        assert!(snips["solve"].contains('2'));
+    }
+
+    #[test]
+    fn test_strip_tests() {
+        let src = r#"
+#[snippet]
+mod math {
+    pub fn gcd(a: u64, b: u64) -> u64 {
+        debug_assert!(a > 0 || b > 0);
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+
+    #[test]
+    fn test_gcd() {}
+
+    #[bench]
+    fn bench_gcd() {}
+
+    #[cfg(all(test, unix))]
+    mod tests {}
+}
+
+#[snippet(keep_tests)]
+fn lcm(a: u64, b: u64) -> u64 {
+    debug_assert_ne!(b, 0);
+    a / b * b
+}
+"#;
+        for snip in &[snippets(src), verbatim_snippets(src)] {
+            assert_eq!(
+                format_src(&snip["math"]).unwrap(),
+                format_src(
+                    "mod math { pub fn gcd(a: u64, b: u64) -> u64 { \
+                     if b == 0 { a } else { gcd(b, a % b) } } }"
+                )
+                .unwrap()
+            );
+            assert!(snip["lcm"].contains("debug_assert_ne"));
+        }
+    }
+
+    #[test]
+    fn test_skip() {
+        let src = r#"
+#[snippet]
+mod math {
+    pub fn gcd(a: u64, b: u64) -> u64 {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+
+    #[snippet_skip]
+    pub fn dump(a: u64) {
+        eprintln!("{}", a);
+    }
+}
+
+struct S;
+
+impl S {
+    #[snippet(name = "new", wrap_impl)]
+    fn new() -> S {
+        S
+    }
+
+    #[snippet(skip)]
+    fn debug(&self) {}
+}
+
+#[snippet("getter")]
+impl S {
+    fn get(&self) -> u64 { 0 }
+
+    #[cargo_snippet::snippet_skip]
+    fn debug2(&self) {}
+}
+"#;
+        for snip in &[snippets(src), verbatim_snippets(src)] {
+            assert_eq!(
+                format_src(&snip["math"]).unwrap(),
+                format_src(
+                    "mod math { pub fn gcd(a: u64, b: u64) -> u64 { \
+                     if b == 0 { a } else { gcd(b, a % b) } } }"
+                )
+                .unwrap()
+            );
+            assert!(!snip.contains_key("debug"));
+            assert!(!snip["new"].contains("debug"));
+            assert!(!snip["getter"].contains("debug"));
+            assert!(snip["getter"].contains("get"));
+        }
+    }
 }
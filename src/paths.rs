@@ -0,0 +1,556 @@
+use proc_macro2::Span;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+use syn::{Block, File, Ident, Item, ItemMod, Path, PathSegment, Stmt, UseTree, Visibility};
+
+use crate::source::{Edit, Source};
+use std::collections::BTreeSet;
+
+// Rewrites `crate::`, `self::` and `super::` paths in a snippet extracted out of its module.
+// Items in other modules are expected to be emitted at the top level as snippets,
+// so module segments are removed. e.g. `crate::math::gcd` -> `gcd`
+// Modules which are a part of the snippet itself (e.g. `#[snippet] mod math { ... }`) are kept.
+pub struct PathRewriter<'a> {
+    // Known modules of the crate. If empty, lowercase segments are considered as modules.
+    modules: &'a BTreeSet<Vec<String>>,
+    // Module where the snippet is defined
+    module_path: &'a [String],
+    // Modules emitted as they are, relative to `module_path`.
+    // e.g. `[["math"]]` for `mod math` and `[[]]` for a whole file.
+    kept: Vec<Vec<String>>,
+    enabled: bool,
+}
+
+// What to do with a `use` declaration.
+enum UseAction {
+    Keep,
+    // It imports from the module where it is, which is meaningless.
+    Remove,
+    // Replace leading `n` segments with the new prefix.
+    Rewrite(usize, Vec<String>),
+}
+
+impl<'a> PathRewriter<'a> {
+    pub fn new(
+        modules: &'a BTreeSet<Vec<String>>,
+        module_path: &'a [String],
+        kept: Vec<Vec<String>>,
+    ) -> Self {
+        PathRewriter {
+            modules,
+            module_path,
+            kept,
+            enabled: true,
+        }
+    }
+
+    // Leave paths as they are. e.g. the module tree is mirrored with `wrap_mod`.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    fn is_module(&self, path: &[String]) -> bool {
+        if self.modules.is_empty() {
+            matches!(path.last().and_then(|s| s.chars().next()), Some(c) if c.is_lowercase())
+        } else {
+            self.modules.contains(path)
+        }
+    }
+
+    // Resolve leading `crate`, `self`, `super` and module segments of `segments` which appear in `current`.
+    // `current` is a module path relative to `module_path`.
+    // Returns the number of the resolved segments and the absolute module path.
+    // The last segment isn't resolved unless `all_modules` because it's an item.
+    fn resolve(
+        &self,
+        segments: &[String],
+        current: &[String],
+        all_modules: bool,
+    ) -> Option<(usize, Vec<String>)> {
+        let mut base = self
+            .module_path
+            .iter()
+            .chain(current.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut i = match segments.first().map(|s| s.as_str()) {
+            Some("crate") => {
+                base.clear();
+                1
+            }
+            Some("self") => 1,
+            Some("super") => 0,
+            _ => return None,
+        };
+        while segments.get(i).map(|s| s.as_str()) == Some("super") {
+            base.pop()?;
+            i += 1;
+        }
+
+        let limit = if all_modules {
+            segments.len()
+        } else {
+            segments.len().saturating_sub(1)
+        };
+        while i < limit {
+            let mut next = base.clone();
+            next.push(segments[i].clone());
+            if !self.is_module(&next) {
+                break;
+            }
+            base = next;
+            i += 1;
+        }
+
+        if !all_modules && i >= segments.len() {
+            return None;
+        }
+        Some((i, base))
+    }
+
+    // Path from `current` to the absolute module `target` in the output.
+    fn new_prefix(&self, target: &[String], current: &[String]) -> Vec<String> {
+        let output = match target.strip_prefix(self.module_path) {
+            Some(rest) if self.kept.iter().any(|k| rest.starts_with(k)) => rest,
+            // Flattened
+            _ => &[],
+        };
+        let common = current
+            .iter()
+            .zip(output.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        (common..current.len())
+            .map(|_| "super".to_string())
+            .chain(output[common..].iter().cloned())
+            .collect()
+    }
+
+    // (number of segments to replace, new prefix) for a path
+    fn rewrite_path(
+        &self,
+        segments: &[String],
+        current: &[String],
+    ) -> Option<(usize, Vec<String>)> {
+        let (n, target) = self.resolve(segments, current, false)?;
+        Some((n, self.new_prefix(&target, current)))
+    }
+
+    fn rewrite_use(&self, tree: &UseTree, current: &[String]) -> UseAction {
+        let segments = use_prefix(tree);
+        let (n, target) = match self.resolve(&segments, current, true) {
+            Some(resolved) => resolved,
+            None => return UseAction::Keep,
+        };
+        let prefix = self.new_prefix(&target, current);
+        if prefix.is_empty() && n == segments.len() {
+            if has_rename(tree) {
+                UseAction::Rewrite(n, vec!["self".to_string()])
+            } else {
+                UseAction::Remove
+            }
+        } else {
+            UseAction::Rewrite(n, prefix)
+        }
+    }
+
+    // Rewrite the syntax tree by `f`. e.g. `rewriter.rewrite(|v| v.visit_item_mut(&mut item))`
+    pub fn rewrite(&self, f: impl FnOnce(&mut dyn VisitMut)) {
+        if !self.enabled {
+            return;
+        }
+        let mut visitor = MutVisitor {
+            rewriter: self,
+            current: Vec::new(),
+        };
+        f(&mut visitor);
+    }
+
+    // Edits to rewrite paths in the original source text. `f` visits the syntax tree.
+    pub fn edits<'ast>(&self, source: &Source, f: impl FnOnce(&mut dyn Visit<'ast>)) -> Vec<Edit> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut visitor = EditVisitor {
+            rewriter: self,
+            source,
+            current: Vec::new(),
+            edits: Vec::new(),
+        };
+        f(&mut visitor);
+        visitor.edits
+    }
+}
+
+// Leading path segments of a `use` tree
+fn use_prefix(tree: &UseTree) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut tree = tree;
+    while let UseTree::Path(ref path) = tree {
+        segments.push(path.ident.to_string());
+        tree = &path.tree;
+    }
+    segments
+}
+
+// `tree` without leading `n` segments
+fn strip_use_prefix(tree: &UseTree, n: usize) -> &UseTree {
+    let mut rest = tree;
+    for _ in 0..n {
+        if let UseTree::Path(ref path) = rest {
+            rest = &path.tree;
+        }
+    }
+    rest
+}
+
+fn has_rename(tree: &UseTree) -> bool {
+    match tree {
+        UseTree::Path(ref path) => has_rename(&path.tree),
+        UseTree::Rename(_) => true,
+        UseTree::Group(ref group) => group.items.iter().any(has_rename),
+        _ => false,
+    }
+}
+
+fn path_idents(path: &Path) -> Vec<String> {
+    path.segments.iter().map(|s| s.ident.to_string()).collect()
+}
+
+// Rewrites the syntax tree.
+struct MutVisitor<'a, 'b> {
+    rewriter: &'b PathRewriter<'a>,
+    current: Vec<String>,
+}
+
+impl<'a, 'b> MutVisitor<'a, 'b> {
+    // Returns false if `item` should be removed.
+    fn rewrite_use_item(&self, item: &mut Item) -> bool {
+        let item_use = match item {
+            Item::Use(ref mut item_use) => item_use,
+            _ => return true,
+        };
+        match self.rewriter.rewrite_use(&item_use.tree, &self.current) {
+            UseAction::Keep => true,
+            UseAction::Remove => false,
+            UseAction::Rewrite(n, prefix) => {
+                item_use.tree = replace_use_prefix(&item_use.tree, n, &prefix);
+                true
+            }
+        }
+    }
+}
+
+fn replace_use_prefix(tree: &UseTree, n: usize, prefix: &[String]) -> UseTree {
+    let rest = strip_use_prefix(tree, n);
+    prefix.iter().rev().fold(rest.clone(), |tree, segment| {
+        UseTree::Path(syn::UsePath {
+            ident: Ident::new(segment, Span::call_site()),
+            colon2_token: Default::default(),
+            tree: Box::new(tree),
+        })
+    })
+}
+
+impl<'a, 'b> VisitMut for MutVisitor<'a, 'b> {
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        let segments = path_idents(path);
+        if let Some((n, prefix)) = self.rewriter.rewrite_path(&segments, &self.current) {
+            let mut new_segments = prefix
+                .iter()
+                .map(|s| PathSegment::from(Ident::new(s, Span::call_site())))
+                .collect::<Punctuated<_, _>>();
+            new_segments.extend(path.segments.iter().skip(n).cloned());
+            path.segments = new_segments;
+            path.leading_colon = None;
+        }
+        visit_mut::visit_path_mut(self, path);
+    }
+
+    // `pub(crate)` etc. are valid anywhere.
+    fn visit_visibility_mut(&mut self, _: &mut Visibility) {}
+
+    fn visit_file_mut(&mut self, file: &mut File) {
+        file.items.retain_mut(|item| self.rewrite_use_item(item));
+        visit_mut::visit_file_mut(self, file);
+    }
+
+    fn visit_item_mod_mut(&mut self, item_mod: &mut ItemMod) {
+        self.current.push(item_mod.ident.to_string());
+        if let Some((_, ref mut items)) = item_mod.content {
+            items.retain_mut(|item| self.rewrite_use_item(item));
+        }
+        visit_mut::visit_item_mod_mut(self, item_mod);
+        self.current.pop();
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block.stmts.retain_mut(|stmt| match stmt {
+            Stmt::Item(ref mut item) => self.rewrite_use_item(item),
+            _ => true,
+        });
+        visit_mut::visit_block_mut(self, block);
+    }
+}
+
+// Collects edits for the original source text.
+struct EditVisitor<'a, 'b, 'c> {
+    rewriter: &'b PathRewriter<'a>,
+    source: &'c Source<'c>,
+    current: Vec<String>,
+    edits: Vec<Edit>,
+}
+
+impl<'a, 'b, 'c> EditVisitor<'a, 'b, 'c> {
+    // Replace text from `start` to the beginning of `rest`.
+    fn replace_prefix(&mut self, start: Span, rest: Span, prefix: &[String]) {
+        let range = self.source.range(start).start..self.source.range(rest).start;
+        let replacement = prefix
+            .iter()
+            .map(|s| format!("{}::", s))
+            .collect::<String>();
+        self.edits.push(Edit { range, replacement });
+    }
+
+    fn use_items<'ast>(&mut self, items: impl IntoIterator<Item = &'ast Item>) {
+        for item in items {
+            if let Item::Use(ref item_use) = item {
+                match self.rewriter.rewrite_use(&item_use.tree, &self.current) {
+                    UseAction::Keep => (),
+                    UseAction::Remove => {
+                        let range = self.source.range(item_use.span());
+                        self.edits
+                            .push(Edit::remove(self.source.removal_range(range)));
+                    }
+                    UseAction::Rewrite(n, prefix) => {
+                        let rest = strip_use_prefix(&item_use.tree, n);
+                        let leading = item_use
+                            .leading_colon
+                            .map(|c| c.span())
+                            .unwrap_or_else(|| item_use.tree.span());
+                        self.replace_prefix(leading, rest.span(), &prefix);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, 'b, 'c, 'ast> Visit<'ast> for EditVisitor<'a, 'b, 'c> {
+    fn visit_path(&mut self, path: &'ast Path) {
+        let segments = path_idents(path);
+        if let Some((n, prefix)) = self.rewriter.rewrite_path(&segments, &self.current) {
+            let start = path
+                .leading_colon
+                .map(|c| c.span())
+                .unwrap_or_else(|| path.segments[0].ident.span());
+            self.replace_prefix(start, path.segments[n].ident.span(), &prefix);
+        }
+        visit::visit_path(self, path);
+    }
+
+    fn visit_visibility(&mut self, _: &'ast Visibility) {}
+
+    fn visit_file(&mut self, file: &'ast File) {
+        self.use_items(&file.items);
+        visit::visit_file(self, file);
+    }
+
+    fn visit_item_mod(&mut self, item_mod: &'ast ItemMod) {
+        self.current.push(item_mod.ident.to_string());
+        if let Some((_, ref items)) = item_mod.content {
+            self.use_items(items);
+        }
+        visit::visit_item_mod(self, item_mod);
+        self.current.pop();
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.use_items(block.stmts.iter().filter_map(|stmt| match stmt {
+            Stmt::Item(ref item) => Some(item),
+            _ => None,
+        }));
+        visit::visit_block(self, block);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PathRewriter;
+    use crate::source::Source;
+    use quote::ToTokens;
+    use std::collections::BTreeSet;
+
+    fn modules(paths: &[&str]) -> BTreeSet<Vec<String>> {
+        paths
+            .iter()
+            .map(|p| p.split("::").map(|s| s.to_string()).collect())
+            .collect()
+    }
+
+    fn path(module_path: &str) -> Vec<String> {
+        module_path
+            .split("::")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    fn tokens(src: &str) -> String {
+        syn::parse_file(src)
+            .unwrap()
+            .into_token_stream()
+            .to_string()
+    }
+
+    fn rewrite(rewriter: &PathRewriter, src: &str) -> String {
+        let mut file = syn::parse_file(src).unwrap();
+        rewriter.rewrite(|v| v.visit_file_mut(&mut file));
+        file.into_token_stream().to_string()
+    }
+
+    #[test]
+    fn test_rewrite_paths() {
+        let modules = modules(&["math", "algo"]);
+        let module_path = path("algo");
+        let rewriter = PathRewriter::new(&modules, &module_path, Vec::new());
+
+        assert_eq!(
+            rewrite(
+                &rewriter,
+                "fn f() -> u64 { crate::math::gcd(4, 6) + super::math::lcm(2, 3) + self::g() }"
+            ),
+            tokens("fn f() -> u64 { gcd(4, 6) + lcm(2, 3) + g() }")
+        );
+        // Associated items and other crates are left.
+        assert_eq!(
+            rewrite(
+                &rewriter,
+                "fn f() { crate::math::ModInt::new(1); ::std::mem::swap(&mut 1, &mut 2); }"
+            ),
+            tokens("fn f() { ModInt::new(1); ::std::mem::swap(&mut 1, &mut 2); }")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths_unknown_modules() {
+        // Lowercase segments are modules if no module is known.
+        let modules = BTreeSet::new();
+        let module_path = path("algo::tree");
+        let rewriter = PathRewriter::new(&modules, &module_path, Vec::new());
+
+        assert_eq!(
+            rewrite(
+                &rewriter,
+                "fn f() { super::super::math::Point::new(); crate::math::gcd(); }"
+            ),
+            tokens("fn f() { Point::new(); gcd(); }")
+        );
+        // Out of the crate root
+        assert_eq!(
+            rewrite(&rewriter, "fn f() { super::super::super::g(); }"),
+            tokens("fn f() { super::super::super::g(); }")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_nested_module() {
+        let modules = modules(&["math", "algo", "algo::tree"]);
+        let module_path = path("algo");
+        let rewriter = PathRewriter::new(&modules, &module_path, vec![path("tree")]);
+
+        // `tree` is emitted as is, so paths into it are kept relative to where they are.
+        let src = r#"
+mod tree {
+    pub fn g() {
+        crate::algo::tree::h();
+        super::tree::h();
+        self::h();
+        crate::math::gcd(1, 2);
+    }
+    pub fn h() {}
+}
+"#;
+        assert_eq!(
+            rewrite(&rewriter, src),
+            tokens(
+                r#"
+mod tree {
+    pub fn g() {
+        h();
+        h();
+        h();
+        super::gcd(1, 2);
+    }
+    pub fn h() {}
+}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_use() {
+        let modules = modules(&["math", "algo", "algo::tree"]);
+        let module_path = path("algo");
+        let rewriter = PathRewriter::new(&modules, &module_path, vec![path("tree")]);
+
+        let src = r#"
+use crate::math::gcd;
+use super::math::{lcm, ModInt};
+use crate::math::gcd as g;
+use self::tree::Node;
+use std::io::Read;
+mod tree {
+    use super::super::math::gcd;
+    use crate::algo::tree::Node as N;
+    pub struct Node;
+}
+"#;
+        // Imports from flattened modules are meaningless except for renames.
+        assert_eq!(
+            rewrite(&rewriter, src),
+            tokens(
+                r#"
+use self::gcd as g;
+use tree::Node;
+use std::io::Read;
+mod tree {
+    use super::gcd;
+    use self::Node as N;
+    pub struct Node;
+}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_disabled() {
+        let modules = modules(&["math", "algo"]);
+        let module_path = path("algo");
+        let mut rewriter = PathRewriter::new(&modules, &module_path, Vec::new());
+        rewriter.disable();
+
+        let src = "use crate::math::gcd;\nfn f() { crate::math::gcd(1, 2); }";
+        assert_eq!(rewrite(&rewriter, src), tokens(src));
+    }
+
+    #[test]
+    fn test_edits() {
+        let modules = modules(&["math", "algo"]);
+        let module_path = path("algo");
+        let rewriter = PathRewriter::new(&modules, &module_path, Vec::new());
+
+        let src = "use super::math::gcd as g;\nuse crate::math::lcm;\n\nfn f() -> u64 {\n    // comment\n    crate::math::gcd(1, 2) + self::h()\n}\n";
+        let file = syn::parse_file(src).unwrap();
+        let source = Source::new(src);
+        let edits = rewriter.edits(&source, |v| v.visit_file(&file));
+        assert_eq!(
+            source.slice_with_edits(0..src.len(), &edits),
+            "use self::gcd as g;\n\nfn f() -> u64 {\n    // comment\n    gcd(1, 2) + h()\n}\n"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use lazy_static::lazy_static;
+use proc_macro2::{TokenStream, TokenTree};
+use regex::Regex;
+
+use crate::source::{Edit, Source};
+use std::collections::BTreeSet;
+
+// A placeholder is written as an identifier `__snip_<number>_<name>` so that it compiles as is.
+// e.g. `__snip_1_n` becomes `${1:n}` in editors.
+lazy_static! {
+    static ref MARKER: Regex = Regex::new(r"\b__snip_(\d+)_(\w+)\b").unwrap();
+}
+
+pub fn marker(number: usize, name: &str) -> String {
+    format!("__snip_{}_{}", number, name)
+}
+
+// A part of content split at placeholders.
+#[derive(Debug, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    // `is_first` is false for the following occurrences of the same number, which are usually mirrors.
+    Tabstop {
+        number: usize,
+        name: &'a str,
+        is_first: bool,
+    },
+}
+
+pub fn split(content: &str) -> Vec<Segment<'_>> {
+    let mut seen = BTreeSet::new();
+    let mut res = Vec::new();
+    let mut pos = 0;
+    for caps in MARKER.captures_iter(content) {
+        let m = caps.get(0).unwrap();
+        if pos < m.start() {
+            res.push(Segment::Text(&content[pos..m.start()]));
+        }
+        let number = caps[1].parse().unwrap_or(0);
+        res.push(Segment::Tabstop {
+            number,
+            name: caps.get(2).unwrap().as_str(),
+            is_first: seen.insert(number),
+        });
+        pos = m.end();
+    }
+    if pos < content.len() {
+        res.push(Segment::Text(&content[pos..]));
+    }
+    res
+}
+
+// Replace each placeholder by `tabstop(number, name, is_first)`.
+pub fn expand(content: &str, tabstop: impl Fn(usize, &str, bool) -> String) -> String {
+    split(content)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.to_string(),
+            Segment::Tabstop {
+                number,
+                name,
+                is_first,
+            } => tabstop(number, name, is_first),
+        })
+        .collect()
+}
+
+// `${1:n}` for the first occurrence and `$1` for the rest.
+// This is the syntax of VSCode, UltiSnips and neosnippet.
+pub fn expand_tabstops(content: &str) -> String {
+    expand(content, |number, name, is_first| {
+        if is_first {
+            format!("${{{}:{}}}", number, name)
+        } else {
+            format!("${}", number)
+        }
+    })
+}
+
+// Replace placeholders by their names for outputs without tabstops.
+pub fn strip(content: &str) -> String {
+    expand(content, |_, name, _| name.to_string())
+}
+
+// Turn identifiers in `names` into placeholders numbered from 1 in the order of `names`.
+// Identifiers in string literals and comments are not changed.
+pub fn mark(content: &str, names: &[String]) -> String {
+    let tokens = match content.parse::<TokenStream>() {
+        Ok(tokens) => tokens,
+        Err(_) => return content.to_string(),
+    };
+    let source = Source::new(content);
+    let mut edits = Vec::new();
+    collect_edits(&source, tokens, names, &mut edits);
+    source.slice_with_edits(0..content.len(), &edits)
+}
+
+fn collect_edits(source: &Source, tokens: TokenStream, names: &[String], edits: &mut Vec<Edit>) {
+    for tok in tokens {
+        match tok {
+            TokenTree::Ident(ref ident) => {
+                let ident_str = ident.to_string();
+                if let Some(i) = names.iter().position(|n| *n == ident_str) {
+                    edits.push(Edit {
+                        range: source.range(ident.span()),
+                        replacement: marker(i + 1, &ident_str),
+                    });
+                }
+            }
+            TokenTree::Group(ref g) => collect_edits(source, g.stream(), names, edits),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{expand_tabstops, mark, split, strip, Segment};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_mark() {
+        let content = "// n\nfn f(n: usize) -> Vec<u64> {\n    vec![0; n] // \"n\"\n}\n";
+        assert_eq!(
+            mark(content, &names(&["n", "u64"])),
+            "// n\nfn f(__snip_1_n: usize) -> Vec<__snip_2_u64> {\n    vec![0; __snip_1_n] // \"n\"\n}\n"
+        );
+        // Only whole identifiers are marked.
+        assert_eq!(
+            mark("let nn = n + r#n;", &names(&["n"])),
+            "let nn = __snip_1_n + r#n;"
+        );
+        // Unbalanced delimiters can't be tokenized.
+        assert_eq!(mark("fn f(n: usize {", &names(&["n"])), "fn f(n: usize {");
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("__snip_1_n+__snip_2_m x__snip_1_n __snip_1_n"),
+            vec![
+                Segment::Tabstop {
+                    number: 1,
+                    name: "n",
+                    is_first: true
+                },
+                Segment::Text("+"),
+                Segment::Tabstop {
+                    number: 2,
+                    name: "m",
+                    is_first: true
+                },
+                Segment::Text(" x__snip_1_n "),
+                Segment::Tabstop {
+                    number: 1,
+                    name: "n",
+                    is_first: false
+                },
+            ]
+        );
+        assert_eq!(split(""), Vec::new());
+        assert_eq!(split("__snip_x_n"), vec![Segment::Text("__snip_x_n")]);
+    }
+
+    #[test]
+    fn test_expand_tabstops() {
+        let marked = "fn f(__snip_1_n: usize) { __snip_2_dp_table[__snip_1_n] }";
+        assert_eq!(
+            expand_tabstops(marked),
+            "fn f(${1:n}: usize) { ${2:dp_table}[$1] }"
+        );
+        assert_eq!(strip(marked), "fn f(n: usize) { dp_table[n] }");
+    }
+}
@@ -0,0 +1,227 @@
+use syn::spanned::Spanned;
+use syn::{parse_file, Item, TraitItem, Type};
+
+use crate::analysis::{item_name, item_references, References};
+use crate::source::{Edit, Source};
+use std::collections::BTreeSet;
+
+// Last identifier of a type path. e.g. `SegTree` for `SegTree<T>`.
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(ref p) => p.path.segments.last().map(|s| s.ident.to_string()),
+        Type::Reference(ref r) => type_name(&r.elem),
+        Type::Paren(ref p) => type_name(&p.elem),
+        Type::Group(ref g) => type_name(&g.elem),
+        _ => None,
+    }
+}
+
+// Names of methods declared in a trait.
+fn trait_methods(item: &Item) -> Vec<String> {
+    match item {
+        Item::Trait(ref item_trait) => item_trait
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                TraitItem::Method(ref m) => Some(m.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Whether an item is kept regardless of reachability.
+fn is_always_kept(item: &Item) -> bool {
+    match item {
+        Item::Use(_) | Item::ExternCrate(_) | Item::ForeignMod(_) | Item::Verbatim(_) => true,
+        // Macro invocation at top level.
+        Item::Macro(ref m) => m.ident.is_none(),
+        _ => false,
+    }
+}
+
+// Remove top level functions, impls, consts, types, etc. which are not reachable from `roots`.
+// An item is reachable if its name is referenced by a root or a reachable item.
+// A trait is also reachable if one of its methods is called, since a method call doesn't name the trait.
+// An `impl` is reachable if its self type is, or its trait is and the self type is not defined in `src`.
+// Kept items are left byte-for-byte.
+pub fn prune(src: &str, roots: &References) -> Result<String, syn::parse::Error> {
+    let file = parse_file(src)?;
+    let source = Source::new(src);
+
+    let defined = file
+        .items
+        .iter()
+        .filter_map(item_name)
+        .collect::<BTreeSet<_>>();
+
+    let mut reachable = roots.idents.clone();
+    let mut called = roots.methods.clone();
+    let mut kept = vec![false; file.items.len()];
+
+    loop {
+        let mut updated = false;
+        for (item, kept) in file.items.iter().zip(kept.iter_mut()) {
+            if *kept {
+                continue;
+            }
+
+            let is_reachable = is_always_kept(item)
+                || match item {
+                    Item::Impl(ref item_impl) => {
+                        let self_ty = type_name(&item_impl.self_ty);
+                        let trait_name = item_impl
+                            .trait_
+                            .as_ref()
+                            .and_then(|(_, path, _)| path.segments.last())
+                            .map(|s| s.ident.to_string());
+                        match self_ty {
+                            Some(ref ty) if defined.contains(ty) => reachable.contains(ty),
+                            // e.g. `impl Trait for i64` or a blanket impl
+                            _ => match trait_name {
+                                Some(ref t) => !defined.contains(t) || reachable.contains(t),
+                                None => true,
+                            },
+                        }
+                    }
+                    Item::Trait(ref item_trait) => {
+                        reachable.contains(&item_trait.ident.to_string())
+                            // Identifiers in macro invocations may be method names as well.
+                            || trait_methods(item)
+                                .iter()
+                                .any(|m| called.contains(m) || reachable.contains(m))
+                    }
+                    _ => matches!(item_name(item), Some(ref name) if reachable.contains(name)),
+                };
+
+            if is_reachable {
+                *kept = true;
+                updated = true;
+                // A trait kept for its methods makes its impls reachable.
+                reachable.extend(item_name(item));
+                let refs = item_references(item);
+                reachable.extend(refs.idents);
+                called.extend(refs.methods);
+            }
+        }
+        if !updated {
+            break;
+        }
+    }
+
+    let edits = file
+        .items
+        .iter()
+        .zip(kept.iter())
+        .filter(|&(_, kept)| !kept)
+        .map(|(item, _)| Edit::remove(source.removal_range(source.range(item.span()))))
+        .collect::<Vec<_>>();
+
+    Ok(source.slice_with_edits(0..src.len(), &edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::prune;
+    use crate::analysis::References;
+    use crate::writer::format_src;
+
+    fn roots(idents: &[&str], methods: &[&str]) -> References {
+        References {
+            idents: idents.iter().map(|s| s.to_string()).collect(),
+            methods: methods.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let src = r#"
+use std::ops::Add;
+fn gcd(a: u64, b: u64) -> u64 { if b == 0 { a } else { gcd(b, a % b) } }
+fn lcm(a: u64, b: u64) -> u64 { a / gcd(a, b) * b }
+fn unused() {}
+const MOD: u64 = 1_000_000_007;
+#[derive(Clone, Copy)]
+struct ModInt(u64);
+impl Add for ModInt {
+    type Output = ModInt;
+    fn add(self, other: ModInt) -> ModInt { ModInt((self.0 + other.0) % MOD) }
+}
+struct Unused;
+impl Unused { fn new() -> Self { Unused } }
+trait Monoid {}
+impl Monoid for u64 {}
+"#;
+
+        let pruned = prune(src, &roots(&["lcm", "ModInt", "main"], &[])).unwrap();
+        assert_eq!(
+            format_src(&pruned).unwrap(),
+            format_src(
+                r#"
+use std::ops::Add;
+fn gcd(a: u64, b: u64) -> u64 { if b == 0 { a } else { gcd(b, a % b) } }
+fn lcm(a: u64, b: u64) -> u64 { a / gcd(a, b) * b }
+const MOD: u64 = 1_000_000_007;
+#[derive(Clone, Copy)]
+struct ModInt(u64);
+impl Add for ModInt {
+    type Output = ModInt;
+    fn add(self, other: ModInt) -> ModInt { ModInt((self.0 + other.0) % MOD) }
+}
+"#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prune_method_call() {
+        let src = r#"
+trait Sq { fn sq(self) -> Self; }
+impl Sq for u64 { fn sq(self) -> Self { self * self } }
+trait Cube { fn cube(self) -> Self; }
+impl Cube for u64 { fn cube(self) -> Self { self * self * self } }
+trait Half { fn half(self) -> Self; }
+impl Half for u64 { fn half(self) -> Self { self / 2 } }
+fn f(x: u64) -> u64 { x.half() }
+"#;
+        // `cube` is called in a macro invocation and `half` by a reachable item.
+        let pruned = prune(src, &roots(&["f", "println", "cube"], &["sq"])).unwrap();
+        assert_eq!(pruned, src);
+
+        // Neither the trait nor its impl is reachable.
+        let pruned = prune(src, &roots(&[], &["sq"])).unwrap();
+        assert_eq!(
+            pruned,
+            "\ntrait Sq { fn sq(self) -> Self; }\nimpl Sq for u64 { fn sq(self) -> Self { self * self } }\n"
+        );
+    }
+
+    #[test]
+    fn test_prune_impls() {
+        let src = "macro_rules! unused { () => {} }\n\
+                   thread_local!(static X: u64 = 0);\n\
+                   struct A;\n\
+                   impl PartialEq for &A { fn eq(&self, _: &Self) -> bool { true } }\n\
+                   trait Local {}\n\
+                   impl Local for u64 {}\n\
+                   impl std::fmt::Display for u64 {}\n";
+
+        // Top level macro invocations and impls of external traits for external types are kept.
+        assert_eq!(
+            prune(src, &roots(&[], &[])).unwrap(),
+            "thread_local!(static X: u64 = 0);\nimpl std::fmt::Display for u64 {}\n"
+        );
+        assert_eq!(
+            prune(src, &roots(&["A", "Local"], &[])).unwrap(),
+            "thread_local!(static X: u64 = 0);\n\
+             struct A;\n\
+             impl PartialEq for &A { fn eq(&self, _: &Self) -> bool { true } }\n\
+             trait Local {}\n\
+             impl Local for u64 {}\n\
+             impl std::fmt::Display for u64 {}\n"
+        );
+        assert!(prune("fn f(", &roots(&["f"], &[])).is_err());
+    }
+}
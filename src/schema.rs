@@ -0,0 +1,37 @@
+// Arguments of `#[snippet(...)]`.
+// This module is shared by the proc-macro (src/lib.rs) and the extractor (src/main.rs),
+// so it must not depend on any crate.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    // `key = "..."`
+    Str,
+    // `key`
+    Flag,
+}
+
+pub const KEYS: &[(&str, ValueKind)] = &[
+    ("name", ValueKind::Str),
+    ("include", ValueKind::Str),
+    ("prefix", ValueKind::Str),
+    ("placeholder", ValueKind::Str),
+    ("subst", ValueKind::Str),
+    ("doc_hidden", ValueKind::Flag),
+    ("wrap_impl", ValueKind::Flag),
+    ("verbatim", ValueKind::Flag),
+    ("wrap_mod", ValueKind::Flag),
+    ("keep_tests", ValueKind::Flag),
+    ("skip", ValueKind::Flag),
+];
+
+pub fn lookup(key: &str) -> Option<ValueKind> {
+    KEYS.iter().find(|&&(k, _)| k == key).map(|&(_, kind)| kind)
+}
+
+// e.g. "`name`, `include`, ..." for error messages.
+pub fn key_list() -> String {
+    KEYS.iter()
+        .map(|&(key, _)| format!("`{}`", key))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
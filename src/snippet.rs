@@ -1,15 +1,36 @@
+use syn::ItemUse;
+
+use crate::analysis::defined_names;
+use crate::diagnostics::{Diagnostic, Location};
+use crate::imports::{merge_prefixes, use_leaves};
+use crate::modtree::display_module_path;
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 #[derive(Debug)]
 pub struct SnippetAttributes {
     // A snippet with multiple names is allowed but using dependency is recommended.
     pub names: HashSet<String>,
-    // Dependencies
-    pub uses: HashSet<String>,
+    // The name taken from the item itself (e.g. function name) if it's in `names`.
+    pub default_name: Option<String>,
+    // Dependencies in declaration order
+    pub uses: Vec<String>,
     // Prefix for snippet. It's will be emitted prior to the snippet.
     pub prefix: String,
+    // Identifiers turned into editor placeholders, numbered from 1 in this order.
+    pub placeholders: Vec<String>,
+    // Generic parameters and consts substituted on output. e.g. `[("T", "i64")]`
+    pub subst: Vec<(String, String)>,
     // Whether doc comments associated with this snippet should be hidden or not.
     pub doc_hidden: bool,
+    // Whether an associated item should be emitted inside its enclosing `impl` or `trait` block.
+    pub wrap_impl: bool,
+    // Whether the content should be cut out of the original source text as is.
+    // Ordinary comments and layout are preserved in this mode.
+    pub verbatim: bool,
+    // Whether the content should be emitted inside `pub mod` blocks mirroring `module_path`.
+    pub wrap_mod: bool,
+    // Whether test-only code (e.g. `#[cfg(test)] mod tests`) should be left in the content.
+    pub keep_tests: bool,
 }
 
 #[derive(Debug)]
@@ -17,70 +38,540 @@ pub struct Snippet {
     pub attrs: SnippetAttributes,
     // Snippet content (Not formated)
     pub content: String,
+    // Where the item is defined
+    pub location: Option<Location>,
+    // Lines of the item in the file. 1-indexed and inclusive
+    pub lines: Option<(usize, usize)>,
+    // Path of the module where the item is defined. e.g. `["math"]` for `crate::math`
+    pub module_path: Vec<String>,
+    // Content emitted inside `pub mod` blocks, where paths are left as written.
+    // It's used if this snippet or one depending on it has `wrap_mod`. `None` if it can't be in a module.
+    pub wrapped: Option<String>,
+    // `use` declarations to make the item available at the top level when wrapped.
+    // e.g. `pub use math::gcd;`
+    pub reexports: Vec<String>,
+    // `use` declarations of the module where the item is defined, carried into its `pub mod` block.
+    pub scope_uses: Vec<String>,
+}
+
+#[derive(Default, Clone, Debug)]
+struct Snip {
+    prefixes: Vec<String>,
+    // Content emitted at the top level
+    content: String,
+    // Whether one of the items has `wrap_mod`
+    wrap_mod: bool,
+    // Content emitted inside modules when wrapped. Items which can't be in a module have an empty path.
+    modules: Vec<(Vec<String>, String)>,
+    reexports: Vec<String>,
+    // `use` declarations carried into modules when wrapped
+    scope_uses: Vec<(Vec<String>, String)>,
 }
 
-pub fn process_snippets(snips: &[Snippet]) -> BTreeMap<String, String> {
-    #[derive(Default, Clone, Debug)]
-    struct Snip {
-        prefix: String,
-        content: String,
+// Nested `pub mod` blocks for `wrap_mod`. The root is the top level.
+#[derive(Default)]
+struct ModuleBlock {
+    content: String,
+    // `use` declarations of the module in the original crate
+    uses: Vec<String>,
+    // Names of items in `content`. `None` if it can't be parsed.
+    names: Option<BTreeSet<String>>,
+    // In the order of appearance
+    children: Vec<(String, ModuleBlock)>,
+}
+
+impl ModuleBlock {
+    fn get(&mut self, module_path: &[String]) -> &mut ModuleBlock {
+        let (first, rest) = match module_path.split_first() {
+            Some(split) => split,
+            None => return self,
+        };
+        let i = match self.children.iter().position(|(name, _)| name == first) {
+            Some(i) => i,
+            None => {
+                self.children.push((first.clone(), ModuleBlock::default()));
+                self.children.len() - 1
+            }
+        };
+        self.children[i].1.get(rest)
+    }
+
+    fn child(&self, name: &str) -> Option<&ModuleBlock> {
+        self.children
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, child)| child)
     }
 
-    let mut pre: BTreeMap<String, Snip> = BTreeMap::new();
-    let mut deps: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    fn find(&self, module_path: &[String]) -> Option<&ModuleBlock> {
+        match module_path.split_first() {
+            Some((first, rest)) => self.child(first)?.find(rest),
+            None => Some(self),
+        }
+    }
 
-    for snip in snips {
-        for name in &snip.attrs.names {
-            let s = pre.entry(name.clone()).or_default();
-            s.prefix += &snip.attrs.prefix;
-            s.content += &snip.content;
-
-            for dep in &snip.attrs.uses {
-                deps.entry(name.clone())
-                    .or_insert_with(BTreeSet::new)
-                    .insert(dep.clone());
+    fn defines(&self, name: &str) -> bool {
+        match self.names {
+            Some(ref names) => names.contains(name),
+            None => true,
+        }
+    }
+
+    fn collect_names(&mut self) {
+        self.names = syn::parse_file(&self.content)
+            .ok()
+            .map(|file| defined_names(&file));
+        for (_, child) in &mut self.children {
+            child.collect_names();
+        }
+    }
+
+    // Whether an import `path::last` in `module_path` refers to something in the output.
+    // Imports from other crates are kept.
+    fn is_emitted(&self, module_path: &[String], path: &[String], last: &str) -> bool {
+        let mut names = path.to_vec();
+        if last != "self" && last != "*" {
+            names.extend(last.split(' ').next().map(|s| s.to_string()));
+        }
+        let mut base = module_path.to_vec();
+        let mut rest = names.as_slice();
+        match rest.first().map(|s| s.as_str()) {
+            Some("crate") => {
+                base.clear();
+                rest = &rest[1..];
+            }
+            Some("self") => rest = &rest[1..],
+            Some("super") => {
+                while rest.first().map(|s| s.as_str()) == Some("super") {
+                    if base.pop().is_none() {
+                        return false;
+                    }
+                    rest = &rest[1..];
+                }
+            }
+            Some(first) => {
+                let here = self.find(module_path);
+                // e.g. `std` of `use std::io;`
+                if !matches!(here, Some(m) if m.child(first).is_some() || m.defines(first)) {
+                    return true;
+                }
+            }
+            None => return true,
+        }
+
+        let mut block = match self.find(&base) {
+            Some(block) => block,
+            None => return false,
+        };
+        for name in rest {
+            match block.child(name) {
+                Some(child) => block = child,
+                // e.g. `gcd` or `Dir` of `Dir::Up`
+                None => return block.defines(name),
             }
         }
+        true
     }
 
-    let mut res: BTreeMap<String, Snip> = BTreeMap::new();
+    // `path` is the module path of this block. `root` is the top level.
+    fn write(&self, root: &ModuleBlock, path: &mut Vec<String>, out: &mut String) {
+        let uses = self
+            .uses
+            .iter()
+            .filter_map(|decl| syn::parse_str::<ItemUse>(decl).ok())
+            .flat_map(|item_use| use_leaves(&item_use))
+            .filter(|(p, last, _)| root.is_emitted(path, p, last))
+            .map(|(_, _, decl)| decl)
+            .collect::<Vec<_>>();
+        out.push_str(&merge_prefixes(&uses));
+        out.push_str(&self.content);
+        for (name, child) in &self.children {
+            out.push_str(&format!("pub mod {} {{\n", name));
+            path.push(name.clone());
+            child.write(root, path, out);
+            path.pop();
+            out.push_str("}\n");
+        }
+    }
+}
+
+// Result of dependency resolution.
+#[derive(Default, Debug)]
+pub struct Resolution {
+    // Snippet names in topological order. Dependencies come first.
+    pub order: Vec<String>,
+    // (dependent, missing dependency)
+    pub missing: Vec<(String, String)>,
+    // Dependency cycles. e.g. `["a", "b", "a"]` for a -> b -> a.
+    pub cycles: Vec<Vec<String>>,
+}
 
-    for (name, uses) in &deps {
-        let mut used = HashSet::new();
-        used.insert(name.clone());
-        let mut stack = uses.iter().cloned().collect::<Vec<_>>();
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+// Snippets grouped by name with their dependencies.
+pub struct SnippetGraph {
+    snips: BTreeMap<String, Snip>,
+    deps: BTreeMap<String, Vec<String>>,
+}
 
-        while let Some(dep) = stack.pop() {
-            if !used.contains(&dep) {
-                used.insert(dep.clone());
-                if let Some(c) = &pre.get(&dep) {
-                    // *res.entry(name.clone()).or_insert_with(String::new) += c.as_str();
-                    let s = res.entry(name.clone()).or_default();
-                    s.prefix += &c.prefix;
-                    s.content += &c.content;
+impl SnippetGraph {
+    // `extra_deps` are added to dependencies declared by `include`.
+    pub fn new(snips: &[Snippet], extra_deps: &BTreeMap<String, BTreeSet<String>>) -> Self {
+        let mut pre: BTreeMap<String, Snip> = BTreeMap::new();
+        let mut deps: BTreeMap<String, Vec<String>> = BTreeMap::new();
 
-                    if let Some(ds) = deps.get(&dep) {
-                        for d in ds {
-                            if !used.contains(d) {
-                                stack.push(d.clone());
-                            }
+        for snip in snips {
+            for name in &snip.attrs.names {
+                let s = pre.entry(name.clone()).or_default();
+                if !snip.attrs.prefix.is_empty() {
+                    s.prefixes.push(snip.attrs.prefix.clone());
+                }
+                s.content += &snip.content;
+                s.wrap_mod |= snip.attrs.wrap_mod;
+                match snip.wrapped {
+                    Some(ref wrapped) => {
+                        s.modules.push((snip.module_path.clone(), wrapped.clone()));
+                        s.reexports.extend(snip.reexports.iter().cloned());
+                        // Declarations of the crate root are left to prefixes.
+                        if !snip.module_path.is_empty() {
+                            s.scope_uses.extend(
+                                snip.scope_uses
+                                    .iter()
+                                    .map(|u| (snip.module_path.clone(), u.clone())),
+                            );
                         }
                     }
-                } else {
-                    log::warn!("Dependency {} is missing", &dep);
+                    None => s.modules.push((Vec::new(), snip.content.clone())),
+                }
+
+                let d = deps.entry(name.clone()).or_default();
+                for dep in &snip.attrs.uses {
+                    if !d.contains(dep) {
+                        d.push(dep.clone());
+                    }
+                }
+            }
+        }
+
+        for (name, extra) in extra_deps {
+            let d = deps.entry(name.clone()).or_default();
+            for dep in extra {
+                if !d.contains(dep) {
+                    d.push(dep.clone());
                 }
             }
         }
+
+        SnippetGraph { snips: pre, deps }
     }
 
-    for (name, snip) in pre {
-        // Dependency first
-        let s = res.entry(name).or_default();
-        s.prefix += snip.prefix.as_str();
-        s.content += snip.content.as_str();
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.snips.keys()
     }
 
-    res.into_iter()
-        .map(|(k, v)| (k, v.prefix + v.content.as_str()))
+    // Resolve `roots` and their dependencies recursively.
+    // Dependencies are visited in declaration order, so the result is deterministic.
+    pub fn resolve<'a>(&self, roots: impl IntoIterator<Item = &'a str>) -> Resolution {
+        let mut res = Resolution::default();
+        let mut marks = BTreeMap::new();
+        let mut path = Vec::new();
+        for root in roots {
+            self.visit(root, &mut marks, &mut path, &mut res);
+        }
+        res
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &'a str,
+        marks: &mut BTreeMap<&'a str, Mark>,
+        path: &mut Vec<&'a str>,
+        res: &mut Resolution,
+    ) {
+        match marks.get(name) {
+            Some(Mark::Done) => return,
+            Some(Mark::Visiting) => {
+                let start = path.iter().position(|n| *n == name).unwrap_or(0);
+                let mut cycle = path[start..]
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>();
+                cycle.push(name.to_string());
+                res.cycles.push(cycle);
+                return;
+            }
+            None => (),
+        }
+
+        if !self.snips.contains_key(name) {
+            marks.insert(name, Mark::Done);
+            if let Some(dependent) = path.last() {
+                res.missing.push((dependent.to_string(), name.to_string()));
+            }
+            return;
+        }
+
+        marks.insert(name, Mark::Visiting);
+        path.push(name);
+        if let Some(deps) = self.deps.get(name) {
+            for dep in deps {
+                self.visit(dep, marks, path, res);
+            }
+        }
+        path.pop();
+        marks.insert(name, Mark::Done);
+        res.order.push(name.to_string());
+    }
+
+    // Concatenate snippets in `order`. Prefixes and re-exports are emitted first.
+    // Imports in prefixes are merged.
+    // If a snippet has `wrap_mod`, all snippets are emitted inside `pub mod` blocks mirroring the crate,
+    // since wrapped content refers to its dependencies by their paths. Content of the same module is
+    // merged into one block together with the `use` declarations of the module which refer to the output.
+    pub fn concat<'a>(&self, order: impl IntoIterator<Item = &'a String>) -> String {
+        let snips = order
+            .into_iter()
+            .filter_map(|name| self.snips.get(name))
+            .collect::<Vec<_>>();
+        let wrap = snips.iter().any(|snip| snip.wrap_mod);
+
+        let mut prefixes = Vec::new();
+        let mut reexports: Vec<&String> = Vec::new();
+        let mut root = ModuleBlock::default();
+        for snip in snips {
+            prefixes.extend(snip.prefixes.iter());
+            if wrap {
+                for (module_path, content) in &snip.modules {
+                    root.get(module_path).content += content;
+                }
+                for (module_path, decl) in &snip.scope_uses {
+                    let uses = &mut root.get(module_path).uses;
+                    if !uses.contains(decl) {
+                        uses.push(decl.clone());
+                    }
+                }
+                // Items of the same name in different modules can't be re-exported together.
+                for reexport in &snip.reexports {
+                    if !reexports
+                        .iter()
+                        .any(|r| reexported_name(r) == reexported_name(reexport))
+                    {
+                        reexports.push(reexport);
+                    }
+                }
+            } else {
+                root.content += &snip.content;
+            }
+        }
+
+        let mut res = merge_prefixes(prefixes);
+        for reexport in reexports {
+            res += reexport;
+            res.push('\n');
+        }
+        if wrap {
+            root.collect_names();
+        }
+        root.write(&root, &mut Vec::new(), &mut res);
+        res
+    }
+}
+
+// Last path segment of a re-export. e.g. `gcd` for `pub use math::gcd;`
+fn reexported_name(reexport: &str) -> &str {
+    reexport
+        .trim_end_matches(';')
+        .rsplit("::")
+        .next()
+        .unwrap_or(reexport)
+}
+
+// Rotate a cycle so that it starts from the smallest name to report each cycle once.
+fn normalize_cycle(cycle: &[String]) -> Vec<String> {
+    let body = &cycle[..cycle.len() - 1];
+    let start = body
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, n)| n)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let mut res = body[start..]
+        .iter()
+        .chain(body[..start].iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    res.push(res[0].clone());
+    res
+}
+
+// Names of snippets which have an item with `#[snippet(verbatim)]`. They are emitted without rustfmt.
+pub fn verbatim_names(snips: &[Snippet]) -> BTreeSet<String> {
+    snips
+        .iter()
+        .filter(|snip| snip.attrs.verbatim)
+        .flat_map(|snip| snip.attrs.names.iter().cloned())
+        .collect()
+}
+
+// `extra_deps` are added to dependencies declared by `include`.
+pub fn process_snippets(
+    snips: &[Snippet],
+    extra_deps: &BTreeMap<String, BTreeSet<String>>,
+) -> BTreeMap<String, String> {
+    let graph = SnippetGraph::new(snips, extra_deps);
+
+    graph
+        .names()
+        .map(|name| {
+            let resolution = graph.resolve(Some(name.as_str()));
+            (name.clone(), graph.concat(&resolution.order))
+        })
         .collect()
 }
+
+// Report missing dependencies, dependency cycles and items which have the same default name.
+pub fn diagnose(
+    snips: &[Snippet],
+    extra_deps: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<Diagnostic> {
+    let graph = SnippetGraph::new(snips, extra_deps);
+
+    let mut missing = BTreeSet::new();
+    let mut cycles = BTreeSet::new();
+    for name in graph.names() {
+        let resolution = graph.resolve(Some(name.as_str()));
+        missing.extend(resolution.missing);
+        cycles.extend(resolution.cycles.iter().map(|c| normalize_cycle(c)));
+    }
+
+    let mut res = Vec::new();
+    for (dependent, dep) in missing {
+        // Point the item which declares the dependency.
+        let location = snips
+            .iter()
+            .find(|s| s.attrs.names.contains(&dependent) && s.attrs.uses.contains(&dep))
+            .and_then(|s| s.location.clone());
+        res.push(
+            // A warning to keep extraction working as before. `lint` fails on it.
+            Diagnostic::warning(format!(
+                "dependency `{}` of snippet `{}` is not found",
+                dep, dependent
+            ))
+            .at(location),
+        );
+    }
+    for cycle in cycles {
+        res.push(Diagnostic::warning(format!(
+            "dependency cycle: {}",
+            cycle.join(" -> ")
+        )));
+    }
+
+    // Sharing an explicit name is the way to group items, but sharing a default name is likely a mistake.
+    let mut seen: BTreeMap<&String, &Vec<String>> = BTreeMap::new();
+    for snip in snips {
+        if let Some(ref name) = snip.attrs.default_name {
+            if let Some(first) = seen.get(name) {
+                res.push(
+                    Diagnostic::warning(format!(
+                        "snippet name `{}` is already used by an item in `{}`. Specify a name to distinguish them",
+                        name,
+                        display_module_path(first)
+                    ))
+                    .at(snip.location.clone()),
+                );
+            } else {
+                seen.insert(name, &snip.module_path);
+            }
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diagnose, Snippet, SnippetAttributes, SnippetGraph};
+    use std::collections::BTreeMap;
+
+    fn snippet(name: &str, uses: &[&str]) -> Snippet {
+        Snippet {
+            attrs: SnippetAttributes {
+                names: Some(name.to_string()).into_iter().collect(),
+                default_name: None,
+                uses: uses.iter().map(|s| s.to_string()).collect(),
+                prefix: String::new(),
+                placeholders: Vec::new(),
+                subst: Vec::new(),
+                doc_hidden: false,
+                wrap_impl: false,
+                verbatim: false,
+                wrap_mod: false,
+                keep_tests: false,
+            },
+            content: format!("fn {}() {{}}", name),
+            location: None,
+            lines: None,
+            module_path: Vec::new(),
+            wrapped: None,
+            reexports: Vec::new(),
+            scope_uses: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_cycle() {
+        let snips = vec![
+            snippet("a", &["b"]),
+            snippet("b", &["c", "d"]),
+            snippet("c", &["a"]),
+            snippet("d", &[]),
+        ];
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+
+        let resolution = graph.resolve(Some("a"));
+        assert_eq!(resolution.order, vec!["c", "d", "b", "a"]);
+        assert_eq!(resolution.cycles, vec![vec!["a", "b", "c", "a"]]);
+        assert!(resolution.missing.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_missing() {
+        let snips = vec![snippet("a", &["x", "b"]), snippet("b", &["x"])];
+        let graph = SnippetGraph::new(&snips, &BTreeMap::new());
+
+        let resolution = graph.resolve(Some("a"));
+        assert_eq!(resolution.order, vec!["b", "a"]);
+        assert_eq!(resolution.missing, vec![("a".to_string(), "x".to_string())]);
+        assert!(resolution.cycles.is_empty());
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let mut snips = vec![
+            snippet("a", &["x", "b"]),
+            snippet("b", &["a"]),
+            snippet("new", &[]),
+            snippet("new", &[]),
+        ];
+        snips[2].attrs.default_name = Some("new".to_string());
+        snips[3].attrs.default_name = Some("new".to_string());
+
+        let messages = diagnose(&snips, &BTreeMap::new())
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "warning: dependency `x` of snippet `a` is not found",
+                "warning: dependency cycle: a -> b -> a",
+                "warning: snippet name `new` is already used by an item in `crate`. Specify a name to distinguish them",
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use proc_macro2::{LineColumn, Span};
+use std::cmp::Reverse;
+use std::ops::Range;
+
+// A replacement of a byte range in the original source.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl Edit {
+    pub fn remove(range: Range<usize>) -> Self {
+        Edit {
+            range,
+            replacement: String::new(),
+        }
+    }
+}
+
+// Original source text with a line index.
+// This maps span locations (which requires `span-locations` feature of proc-macro2) to byte offsets.
+pub struct Source<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Source { text, line_starts }
+    }
+
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    // Byte offset of `lc`. Line is 1-indexed and column is counted in chars.
+    pub fn offset(&self, lc: LineColumn) -> usize {
+        let line_start = match self.line_starts.get(lc.line.saturating_sub(1)) {
+            Some(&start) => start,
+            None => return self.text.len(),
+        };
+        self.text[line_start..]
+            .char_indices()
+            .nth(lc.column)
+            .map(|(i, _)| line_start + i)
+            .unwrap_or_else(|| self.text.len())
+    }
+
+    // Text of a 1-indexed line without the line break.
+    pub fn line(&self, line: usize) -> &'a str {
+        let start = match self.line_starts.get(line.saturating_sub(1)) {
+            Some(&start) => start,
+            None => return "",
+        };
+        let text = &self.text[start..];
+        let text = &text[..text.find('\n').unwrap_or(text.len())];
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    pub fn range(&self, span: Span) -> Range<usize> {
+        self.offset(span.start())..self.offset(span.end())
+    }
+
+    // Extend `range` so that removing it doesn't leave trailing spaces or a blank line.
+    pub fn removal_range(&self, range: Range<usize>) -> Range<usize> {
+        let bytes = self.text.as_bytes();
+        let mut end = range.end;
+        while end < bytes.len() && (bytes[end] == b' ' || bytes[end] == b'\t') {
+            end += 1;
+        }
+
+        let line_start = self.text[..range.start]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let only_indent_before = self.text[line_start..range.start]
+            .chars()
+            .all(|c| c == ' ' || c == '\t');
+        let newline_len = if self.text[end..].starts_with("\r\n") {
+            2
+        } else if self.text[end..].starts_with('\n') {
+            1
+        } else {
+            0
+        };
+
+        if only_indent_before && newline_len > 0 {
+            line_start..end + newline_len
+        } else {
+            range.start..end
+        }
+    }
+
+    // Extend `range` to the beginning of the line if only whitespaces precede it.
+    // Returns the extended range and the indentation.
+    pub fn expand_to_line_start(&self, range: Range<usize>) -> (Range<usize>, &'a str) {
+        let line_start = self.text[..range.start]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let before = &self.text[line_start..range.start];
+        if before.chars().all(|c| c == ' ' || c == '\t') {
+            (line_start..range.end, before)
+        } else {
+            (range, "")
+        }
+    }
+
+    // Text of `range` with `edits` applied.
+    // Edits outside of `range` or overlapping with a preceding edit are ignored.
+    // Of edits starting at the same position, the longest one is applied. e.g. removal of a whole item.
+    // Edits crossing a boundary of `range` are clipped.
+    pub fn slice_with_edits(&self, range: Range<usize>, edits: &[Edit]) -> String {
+        let mut edits = edits
+            .iter()
+            .filter(|e| e.range.start < range.end && range.start < e.range.end)
+            .map(|e| {
+                (
+                    e.range.start.max(range.start)..e.range.end.min(range.end),
+                    e,
+                )
+            })
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|(r, _)| (r.start, Reverse(r.end)));
+
+        let mut res = String::with_capacity(range.end - range.start);
+        let mut pos = range.start;
+        for (r, edit) in edits {
+            if r.start < pos {
+                continue;
+            }
+            res.push_str(&self.text[pos..r.start]);
+            res.push_str(&edit.replacement);
+            pos = r.end;
+        }
+        res.push_str(&self.text[pos..range.end]);
+        res
+    }
+}
+
+// Remove `indent` from the beginning of each line.
+pub fn dedent(text: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return text.to_string();
+    }
+    text.split('\n')
+        .map(|line| line.strip_prefix(indent).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dedent, Edit, Source};
+    use proc_macro2::LineColumn;
+
+    #[test]
+    fn test_lines() {
+        let source = Source::new("fn a() {}\r\n  é = 1;\nlast");
+        assert_eq!(source.line(1), "fn a() {}");
+        assert_eq!(source.line(2), "  é = 1;");
+        assert_eq!(source.line(3), "last");
+        assert_eq!(source.line(4), "");
+
+        let offset = |line, column| source.offset(LineColumn { line, column });
+        // Columns are counted in chars.
+        assert_eq!(offset(2, 3), 15);
+        assert_eq!(offset(3, 4), source.text().len());
+        assert_eq!(offset(3, 100), source.text().len());
+        assert_eq!(offset(9, 0), source.text().len());
+    }
+
+    #[test]
+    fn test_removal_range() {
+        // The whole line including trailing spaces
+        let source = Source::new("a\n    b;  \nc");
+        assert_eq!(source.removal_range(6..8), 2..11);
+        let source = Source::new("  b;\r\nc");
+        assert_eq!(source.removal_range(2..4), 0..6);
+        // Only trailing spaces if there is other code on the line
+        let source = Source::new("x; b; y");
+        assert_eq!(source.removal_range(3..5), 3..6);
+        let source = Source::new("x;\nb;");
+        assert_eq!(source.removal_range(3..5), 3..5);
+    }
+
+    #[test]
+    fn test_slice_with_edits() {
+        let source = Source::new("0123456789");
+        let edit = |range, replacement: &str| Edit {
+            range,
+            replacement: replacement.to_string(),
+        };
+        let edits = vec![
+            edit(5..7, "Z"),
+            edit(2..4, "X"),
+            edit(2..6, "Y"),
+            edit(8..10, "W"),
+            edit(0..1, "V"),
+        ];
+        // `Y` is longer than `X` and `Z` overlaps with it. `W` is clipped and `V` is outside.
+        assert_eq!(source.slice_with_edits(1..9, &edits), "1Y67W");
+        // Edits covering the whole range replace it.
+        assert_eq!(source.slice_with_edits(3..5, &edits), "Y");
+    }
+
+    #[test]
+    fn test_dedent() {
+        assert_eq!(dedent("    a\n  b\n    c", "    "), "a\n  b\nc");
+        assert_eq!(dedent("  a\n", ""), "  a\n");
+    }
+}
@@ -0,0 +1,494 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    Expr, ExprParen, GenericArgument, GenericParam, Generics, ItemConst, Path, PathArguments,
+    QSelf, Type, TypePath, WherePredicate,
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+lazy_static! {
+    // A comma followed by `NAME=` starts the next entry.
+    static ref SEPARATOR: Regex = Regex::new(r",\s*[A-Za-z_]\w*\s*=[^=]").unwrap();
+}
+
+// `T=i64, MOD=998244353` -> `[("T", "i64"), ("MOD", "998244353")]`
+// Other commas belong to values. e.g. `M=HashMap<u64, u64>`
+pub fn parse_subst(s: &str) -> Vec<(String, String)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for m in SEPARATOR.find_iter(s) {
+        parts.push(&s[start..m.start()]);
+        start = m.start() + 1;
+    }
+    parts.push(&s[start..]);
+
+    parts
+        .into_iter()
+        .filter_map(|part| {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next()?.trim();
+            let value = kv.next()?.trim();
+            if key.is_empty() || value.is_empty() {
+                None
+            } else {
+                Some((key.to_string(), value.to_string()))
+            }
+        })
+        .collect()
+}
+
+// Generic parameters and consts to be substituted
+#[derive(Default)]
+struct Collector<'a> {
+    names: BTreeSet<&'a str>,
+    type_params: BTreeSet<String>,
+    const_params: BTreeSet<String>,
+    const_items: BTreeSet<String>,
+}
+
+impl<'a, 'ast> Visit<'ast> for Collector<'a> {
+    fn visit_generic_param(&mut self, param: &'ast GenericParam) {
+        match param {
+            GenericParam::Type(ref p) if self.names.contains(p.ident.to_string().as_str()) => {
+                self.type_params.insert(p.ident.to_string());
+            }
+            GenericParam::Const(ref p) if self.names.contains(p.ident.to_string().as_str()) => {
+                self.const_params.insert(p.ident.to_string());
+            }
+            _ => (),
+        }
+        visit::visit_generic_param(self, param);
+    }
+
+    fn visit_item_const(&mut self, item: &'ast ItemConst) {
+        if self.names.contains(item.ident.to_string().as_str()) {
+            self.const_items.insert(item.ident.to_string());
+        }
+        visit::visit_item_const(self, item);
+    }
+}
+
+// Replace generic parameters by concrete types or values and remove them.
+// Generic arguments for the removed parameters are also removed. e.g. `ModInt<T>` -> `ModInt`
+struct Substituter {
+    types: BTreeMap<String, Type>,
+    consts: BTreeMap<String, Expr>,
+    const_items: BTreeMap<String, Expr>,
+    // Types and traits with removed parameters.
+    // `true` for each removed one among type and const parameters.
+    generic_items: BTreeMap<String, Vec<bool>>,
+    // Functions with removed parameters. They are only matched by `f` or `Self::f`,
+    // so turbofish of other functions with the same name is left.
+    generic_fns: BTreeMap<String, Vec<bool>>,
+}
+
+impl Substituter {
+    fn is_removed(&self, param: &GenericParam) -> bool {
+        match param {
+            GenericParam::Type(ref p) => self.types.contains_key(&p.ident.to_string()),
+            GenericParam::Const(ref p) => self.consts.contains_key(&p.ident.to_string()),
+            GenericParam::Lifetime(_) => false,
+        }
+    }
+
+    // `true` for each removed one among type and const parameters if any is removed.
+    fn removed(&self, generics: &Generics) -> Option<Vec<bool>> {
+        let removed = generics
+            .params
+            .iter()
+            .filter(|p| !matches!(p, GenericParam::Lifetime(_)))
+            .map(|p| self.is_removed(p))
+            .collect::<Vec<_>>();
+        if removed.iter().any(|&r| r) {
+            Some(removed)
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, ident: &syn::Ident, generics: &Generics) {
+        if let Some(removed) = self.removed(generics) {
+            self.generic_items.insert(ident.to_string(), removed);
+        }
+    }
+
+    fn record_fn(&mut self, ident: &syn::Ident, generics: &Generics) {
+        if let Some(removed) = self.removed(generics) {
+            self.generic_fns.insert(ident.to_string(), removed);
+        }
+    }
+
+    // The substituted type if `path` is just a parameter name.
+    fn single_ident(path: &Path) -> Option<String> {
+        match path.segments.first() {
+            Some(seg)
+                if path.leading_colon.is_none()
+                    && path.segments.len() == 1
+                    && seg.arguments.is_empty() =>
+            {
+                Some(seg.ident.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    // `T::zero` -> `<i64>::zero`
+    fn qualify(&self, qself: &mut Option<QSelf>, path: &mut Path) {
+        if qself.is_some() || path.leading_colon.is_some() || path.segments.len() < 2 {
+            return;
+        }
+        let first = &path.segments[0];
+        let ty = match self.types.get(&first.ident.to_string()) {
+            Some(ty) if first.arguments.is_empty() => ty.clone(),
+            _ => return,
+        };
+        *qself = Some(QSelf {
+            lt_token: Default::default(),
+            ty: Box::new(ty),
+            position: 0,
+            as_token: None,
+            gt_token: Default::default(),
+        });
+        path.leading_colon = Some(Default::default());
+        path.segments = path.segments.iter().skip(1).cloned().collect();
+    }
+}
+
+fn parenthesize(expr: Expr) -> Expr {
+    match expr {
+        Expr::Lit(_) | Expr::Path(_) | Expr::Paren(_) => expr,
+        expr => Expr::Paren(ExprParen {
+            attrs: Vec::new(),
+            paren_token: Default::default(),
+            expr: Box::new(expr),
+        }),
+    }
+}
+
+impl VisitMut for Substituter {
+    fn visit_generics_mut(&mut self, generics: &mut Generics) {
+        let params = std::mem::take(&mut generics.params);
+        generics.params = params.into_iter().filter(|p| !self.is_removed(p)).collect();
+        if generics.params.is_empty() {
+            generics.lt_token = None;
+            generics.gt_token = None;
+        }
+
+        if let Some(ref mut where_clause) = generics.where_clause {
+            let predicates = std::mem::take(&mut where_clause.predicates);
+            where_clause.predicates = predicates
+                .into_iter()
+                .filter(|pred| match pred {
+                    WherePredicate::Type(ref pt) => match pt.bounded_ty {
+                        Type::Path(TypePath {
+                            qself: None,
+                            ref path,
+                        }) => !matches!(Self::single_ident(path), Some(ident) if self.types.contains_key(&ident)),
+                        _ => true,
+                    },
+                    _ => true,
+                })
+                .collect::<Punctuated<_, _>>();
+            if where_clause.predicates.is_empty() {
+                generics.where_clause = None;
+            }
+        }
+        visit_mut::visit_generics_mut(self, generics);
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(ref mut tp) = ty {
+            if tp.qself.is_none() {
+                if let Some(new) = Self::single_ident(&tp.path).and_then(|i| self.types.get(&i)) {
+                    *ty = new.clone();
+                    return;
+                }
+            }
+            self.qualify(&mut tp.qself, &mut tp.path);
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let Expr::Path(ref mut ep) = expr {
+            if ep.qself.is_none() {
+                if let Some(new) = Self::single_ident(&ep.path).and_then(|i| self.consts.get(&i)) {
+                    *expr = parenthesize(new.clone());
+                    return;
+                }
+            }
+            self.qualify(&mut ep.qself, &mut ep.path);
+        }
+        visit_mut::visit_expr_mut(self, expr);
+    }
+
+    fn visit_generic_argument_mut(&mut self, arg: &mut GenericArgument) {
+        // A const argument is parsed as a type. e.g. `ModInt<MOD>`
+        if let GenericArgument::Type(Type::Path(TypePath {
+            qself: None,
+            ref path,
+        })) = arg
+        {
+            if let Some(value) = Self::single_ident(path).and_then(|i| self.consts.get(&i)) {
+                let value = match value {
+                    Expr::Lit(_) => value.clone(),
+                    _ => syn::parse_quote!({ #value }),
+                };
+                *arg = GenericArgument::Const(value);
+                return;
+            }
+        }
+        visit_mut::visit_generic_argument_mut(self, arg);
+    }
+
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        let is_local = path.leading_colon.is_none()
+            && (path.segments.len() == 1
+                || path.segments.len() == 2 && path.segments[0].ident == "Self");
+        let len = path.segments.len();
+        for (n, seg) in path.segments.iter_mut().enumerate() {
+            let ident = seg.ident.to_string();
+            let removed = match self.generic_items.get(&ident) {
+                Some(removed) => removed,
+                None => match self.generic_fns.get(&ident) {
+                    Some(removed) if is_local && n + 1 == len => removed,
+                    _ => continue,
+                },
+            };
+            if let PathArguments::AngleBracketed(ref mut args) = seg.arguments {
+                let mut i = 0;
+                let old = std::mem::take(&mut args.args);
+                args.args = old
+                    .into_iter()
+                    .filter(|arg| {
+                        if let GenericArgument::Lifetime(_) = arg {
+                            return true;
+                        }
+                        i += 1;
+                        !removed.get(i - 1).copied().unwrap_or(false)
+                    })
+                    .collect();
+                if args.args.is_empty() {
+                    seg.arguments = PathArguments::None;
+                }
+            }
+        }
+        visit_mut::visit_path_mut(self, path);
+    }
+
+    fn visit_item_const_mut(&mut self, item: &mut ItemConst) {
+        if let Some(value) = self.const_items.get(&item.ident.to_string()) {
+            *item.expr = value.clone();
+        }
+        visit_mut::visit_item_const_mut(self, item);
+    }
+}
+
+// Records items whose generic parameters are removed.
+struct Recorder<'a>(&'a mut Substituter);
+
+impl<'a, 'ast> Visit<'ast> for Recorder<'a> {
+    fn visit_item_struct(&mut self, item: &'ast syn::ItemStruct) {
+        self.0.record(&item.ident, &item.generics);
+        visit::visit_item_struct(self, item);
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast syn::ItemEnum) {
+        self.0.record(&item.ident, &item.generics);
+        visit::visit_item_enum(self, item);
+    }
+
+    fn visit_item_union(&mut self, item: &'ast syn::ItemUnion) {
+        self.0.record(&item.ident, &item.generics);
+        visit::visit_item_union(self, item);
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast syn::ItemTrait) {
+        self.0.record(&item.ident, &item.generics);
+        visit::visit_item_trait(self, item);
+    }
+
+    fn visit_item_type(&mut self, item: &'ast syn::ItemType) {
+        self.0.record(&item.ident, &item.generics);
+        visit::visit_item_type(self, item);
+    }
+
+    fn visit_signature(&mut self, sig: &'ast syn::Signature) {
+        self.0.record_fn(&sig.ident, &sig.generics);
+        visit::visit_signature(self, sig);
+    }
+}
+
+// Substitute generic parameters and consts in a syntax tree by `subst`.
+// `visit` and `visit_mut` visit `node`. e.g. `substitute(&subst, &mut item, |v, i| v.visit_item(i), |v, i| v.visit_item_mut(i))`
+// Only parameters declared in `node` are substituted.
+pub fn substitute<T>(
+    subst: &[(String, String)],
+    node: &mut T,
+    visit: impl for<'ast> Fn(&mut dyn Visit<'ast>, &'ast T),
+    visit_mut: impl FnOnce(&mut dyn VisitMut, &mut T),
+) {
+    if subst.is_empty() {
+        return;
+    }
+    let mut collector = Collector {
+        names: subst.iter().map(|(k, _)| k.as_str()).collect(),
+        ..Collector::default()
+    };
+    visit(&mut collector, node);
+
+    let mut substituter = Substituter {
+        types: BTreeMap::new(),
+        consts: BTreeMap::new(),
+        const_items: BTreeMap::new(),
+        generic_items: BTreeMap::new(),
+        generic_fns: BTreeMap::new(),
+    };
+    for (key, value) in subst {
+        if collector.type_params.contains(key) {
+            match syn::parse_str::<Type>(value) {
+                Ok(ty) => {
+                    substituter.types.insert(key.clone(), ty);
+                }
+                Err(e) => log::warn!("Invalid type `{}` for `{}`: {}", value, key, e),
+            }
+        }
+        if collector.const_params.contains(key) || collector.const_items.contains(key) {
+            match syn::parse_str::<Expr>(value) {
+                Ok(expr) if collector.const_params.contains(key) => {
+                    substituter.consts.insert(key.clone(), expr);
+                }
+                Ok(expr) => {
+                    substituter.const_items.insert(key.clone(), expr);
+                }
+                Err(e) => log::warn!("Invalid value `{}` for `{}`: {}", value, key, e),
+            }
+        }
+    }
+    if substituter.types.is_empty()
+        && substituter.consts.is_empty()
+        && substituter.const_items.is_empty()
+    {
+        return;
+    }
+
+    visit(&mut Recorder(&mut substituter), node);
+    visit_mut(&mut substituter, node);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_subst, substitute};
+    use crate::writer::format_src;
+    use quote::ToTokens;
+
+    fn apply(src: &str, subst: &str) -> String {
+        let mut file = syn::parse_file(src).unwrap();
+        substitute(
+            &parse_subst(subst),
+            &mut file,
+            |v, file| v.visit_file(file),
+            |v, file| v.visit_file_mut(file),
+        );
+        format_src(&file.into_token_stream().to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_subst() {
+        assert_eq!(
+            parse_subst("T=i64, M = HashMap<u64, u64>,MOD=998_244_353"),
+            vec![
+                ("T".to_string(), "i64".to_string()),
+                ("M".to_string(), "HashMap<u64, u64>".to_string()),
+                ("MOD".to_string(), "998_244_353".to_string()),
+            ]
+        );
+        // Entries without a key or a value are ignored.
+        for s in &["", "T", "T=", "=u64", " = "] {
+            assert!(parse_subst(s).is_empty(), "{}", s);
+        }
+        assert_eq!(
+            parse_subst("M=, N=a == b"),
+            vec![("N".to_string(), "a == b".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_substitute() {
+        let src = r#"
+            const MOD: u64 = 1_000_000_007;
+            pub struct ModInt<T, const M: u64> { v: T }
+            impl<T: Copy + From<u64>, const M: u64> ModInt<T, M> where T: Default {
+                fn new(v: u64) -> ModInt<T, M> { ModInt { v: T::from(v % M) } }
+                fn modulus() -> u64 { M + MOD }
+            }
+        "#;
+        assert_eq!(
+            apply(src, "T=u64, M=1 << 20, MOD=998244353"),
+            format_src(
+                r#"const MOD: u64 = 998244353;
+                pub struct ModInt { v: u64 }
+                impl ModInt {
+                    fn new(v: u64) -> ModInt { ModInt { v: <u64>::from(v % (1 << 20)) } }
+                    fn modulus() -> u64 { (1 << 20) + MOD }
+                }
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_substitute_turbofish() {
+        let src = r#"
+            impl Num {
+                fn zero<T: Default>() -> T { T::default() }
+                fn f() -> u64 {
+                    Self::zero::<u64>() + zero::<u64>() + num::zero::<u64>() + "1".parse::<u64>().unwrap()
+                }
+            }
+        "#;
+        // Only calls of `zero` in this scope lose the argument.
+        assert_eq!(
+            apply(src, "T=u64"),
+            format_src(
+                r#"impl Num {
+                    fn zero() -> u64 { <u64>::default() }
+                    fn f() -> u64 {
+                        Self::zero() + zero() + num::zero::<u64>() + "1".parse::<u64>().unwrap()
+                    }
+                }
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_substitute_where_clause() {
+        let src =
+            "fn f<'a, T, U>(x: &'a T, y: U) -> &'a T where T: Clone, U: Copy, Vec<T>: Clone { x }";
+        assert_eq!(
+            apply(src, "T=u8"),
+            format_src(
+                "fn f<'a, U>(x: &'a u8, y: U) -> &'a u8 where U: Copy, Vec<u8>: Clone { x }"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_substitute_unrelated() {
+        // Parameters not declared here are left.
+        let src = "fn f<U>(x: U) -> T { g::<T>(x) }";
+        assert_eq!(apply(src, "T=u64, MOD=7"), format_src(src).unwrap());
+
+        // Invalid values are ignored.
+        let src = "struct S<T, const N: usize>([T; N]);";
+        assert_eq!(apply(src, "T=Vec<, N=1 +"), format_src(src).unwrap());
+    }
+}
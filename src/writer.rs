@@ -1,5 +1,10 @@
+use crate::placeholder::{expand_tabstops, split, Segment};
+use crate::source::dedent;
 use serde_derive::Serialize;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
 
 #[derive(Serialize)]
 struct VScode {
@@ -7,6 +12,20 @@ struct VScode {
     body: Vec<String>,
 }
 
+#[derive(Serialize)]
+struct Vsnip {
+    prefix: Vec<String>,
+    body: Vec<String>,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct Zed {
+    prefix: String,
+    body: String,
+    description: String,
+}
+
 #[cfg(feature = "inner_rustfmt")]
 pub fn format_src(src: &str) -> Option<String> {
     let mut rustfmt_config = rustfmt_nightly::Config::default();
@@ -59,36 +78,77 @@ pub fn format_src(src: &str) -> Option<String> {
     Some(out.replace("\r\n", "\n"))
 }
 
+// Name of the function which statements are wrapped in to be valid as a file.
+pub const BLOCK_FN: &str = "__cargo_snippet_block";
+
+// Whether `content` has statements at the top level (e.g. from `snippet_block!`).
+pub fn is_statements(content: &str) -> bool {
+    syn::parse_file(content).is_err()
+        && syn::parse_str::<syn::File>(&wrap_statements(content)).is_ok()
+}
+
+// `fn __cargo_snippet_block() { ... }` to handle statements as items.
+pub fn wrap_statements(content: &str) -> String {
+    format!("fn {}() {{\n{}\n}}\n", BLOCK_FN, content)
+}
+
+// rustfmt can't format statements at the top level, so format them in a function.
+fn format_statements(src: &str) -> Option<String> {
+    let formatted = format_src(&wrap_statements(src))?;
+    let body = formatted
+        .trim_end()
+        .strip_prefix(&format!("fn {}() {{", BLOCK_FN))?
+        .strip_suffix('}')?
+        .trim_start_matches('\n');
+    Some(dedent(body, "    "))
+}
+
+// Format all snippets by rustfmt except `verbatim` ones, which are left as they are.
+// Snippets which fail to be formatted are dropped.
+pub fn format_snippets(
+    snippets: &BTreeMap<String, String>,
+    verbatim: &BTreeSet<String>,
+) -> BTreeMap<String, String> {
+    snippets
+        .iter()
+        .filter_map(|(name, content)| {
+            let formatted = if verbatim.contains(name) {
+                Some(content.clone())
+            } else if is_statements(content) {
+                format_statements(content)
+            } else {
+                format_src(content)
+            };
+            formatted.map(|formatted| (name.clone(), formatted))
+        })
+        .collect()
+}
+
 pub fn write_neosnippet(snippets: &BTreeMap<String, String>) {
     for (name, content) in snippets.iter() {
-        if let Some(formatted) = format_src(content) {
-            println!("snippet {}", name);
-            for line in formatted.lines() {
-                println!("    {}", line);
-            }
-            println!();
+        println!("snippet {}", name);
+        for line in expand_tabstops(content).lines() {
+            println!("    {}", line);
         }
+        println!();
     }
 }
 
 pub fn write_vscode(snippets: &BTreeMap<String, String>) {
     let vscode: BTreeMap<String, VScode> = snippets
         .iter()
-        .filter_map(|(name, content)| {
-            format_src(content).map(|formatted| {
-                (
-                    name.to_owned(),
-                    VScode {
-                        prefix: name.to_owned(),
-                        body: formatted
-                            .lines()
-                            .map(|l|
-                                // Escape "$" to disable placeholder
-                                l.to_owned().replace("$", "\\$"))
-                            .collect(),
-                    },
-                )
-            })
+        .map(|(name, content)| {
+            (
+                name.to_owned(),
+                VScode {
+                    prefix: name.to_owned(),
+                    // Escape "$" except for placeholders
+                    body: expand_tabstops(&content.replace('$', "\\$"))
+                        .lines()
+                        .map(|l| l.to_owned())
+                        .collect(),
+                },
+            )
         })
         .collect();
 
@@ -97,15 +157,277 @@ pub fn write_vscode(snippets: &BTreeMap<String, String>) {
     }
 }
 
+// In the LSP snippet syntax `\\` is an escape character as well as `$`.
+// vim-vsnip and Zed read it.
+fn escape_lsp(content: &str) -> String {
+    content.replace('\\', "\\\\").replace('$', "\\$")
+}
+
+// vim-vsnip reads VSCode format with the LSP escaping.
+fn vsnip(snippets: &BTreeMap<String, String>) -> BTreeMap<String, Vsnip> {
+    snippets
+        .iter()
+        .map(|(name, content)| {
+            (
+                name.to_owned(),
+                Vsnip {
+                    prefix: vec![name.to_owned()],
+                    body: expand_tabstops(&escape_lsp(content))
+                        .lines()
+                        .map(|l| l.to_owned())
+                        .collect(),
+                    description: name.to_owned(),
+                },
+            )
+        })
+        .collect()
+}
+
+pub fn write_vsnip(snippets: &BTreeMap<String, String>) {
+    if let Ok(json) = serde_json::to_string_pretty(&vsnip(snippets)) {
+        println!("{}", json);
+    }
+}
+
+// Zed's `rust.json`. `body` is a string and there is no `scope` unlike VSCode.
+fn zed(snippets: &BTreeMap<String, String>) -> BTreeMap<String, Zed> {
+    snippets
+        .iter()
+        .map(|(name, content)| {
+            (
+                name.to_owned(),
+                Zed {
+                    prefix: name.to_owned(),
+                    body: expand_tabstops(&escape_lsp(content.trim_end_matches('\n'))),
+                    description: name.to_owned(),
+                },
+            )
+        })
+        .collect()
+}
+
+pub fn write_zed(snippets: &BTreeMap<String, String>) {
+    if let Ok(json) = serde_json::to_string_pretty(&zed(snippets)) {
+        println!("{}", json);
+    }
+}
+
+// A Lua string literal
+fn lua_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+// LuaSnip nodes of a snippet. Placeholders are insert nodes and their mirrors are `rep`.
+fn luasnip_nodes(content: &str) -> Vec<String> {
+    split(content.trim_end_matches('\n'))
+        .into_iter()
+        .map(|segment| match segment {
+            // A table of lines for multi-line text
+            Segment::Text(text) if text.contains('\n') => format!(
+                "t({{ {} }})",
+                text.split('\n')
+                    .map(lua_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Segment::Text(text) => format!("t({})", lua_string(text)),
+            Segment::Tabstop {
+                number,
+                name,
+                is_first: true,
+            } => format!("i({}, {})", number, lua_string(name)),
+            Segment::Tabstop { number, .. } => format!("rep({})", number),
+        })
+        .collect()
+}
+
+// A Lua module which returns LuaSnip snippets. e.g. `require("luasnip").add_snippets("rust", require("snippets"))`
+fn luasnip(snippets: &BTreeMap<String, String>) -> String {
+    let mut res = String::from(
+        "local ls = require(\"luasnip\")\n\
+         local s = ls.snippet\n\
+         local t = ls.text_node\n\
+         local i = ls.insert_node\n\
+         local rep = require(\"luasnip.extras\").rep\n\
+         \n\
+         return {\n",
+    );
+    for (name, content) in snippets.iter() {
+        res += &format!("    s({}, {{\n", lua_string(name));
+        for node in luasnip_nodes(content) {
+            res += &format!("        {},\n", node);
+        }
+        res += "    }),\n";
+    }
+    res += "}\n";
+    res
+}
+
+pub fn write_luasnip(snippets: &BTreeMap<String, String>) {
+    print!("{}", luasnip(snippets));
+}
+
 pub fn write_ultisnips(snippets: &BTreeMap<String, String>) {
     for (name, content) in snippets.iter() {
-        if let Some(formatted) = format_src(content) {
-            println!("snippet {}", name);
-            print!("{}", formatted);
-            println!("endsnippet");
-            println!();
+        println!("snippet {}", name);
+        print!("{}", expand_tabstops(content));
+        println!("endsnippet");
+        println!();
+    }
+}
+
+// `$`, backquotes and backslashes are special in yasnippet.
+fn escape_yasnippet(content: &str) -> String {
+    let mut res = String::with_capacity(content.len());
+    for c in content.chars() {
+        if matches!(c, '$' | '`' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+// A file name for a snippet name. Path separators are replaced.
+fn snippet_file_name(name: &str) -> String {
+    name.replace(['/', '\\'], "_")
+}
+
+fn yasnippet(name: &str, content: &str) -> String {
+    format!(
+        "# -*- mode: snippet -*-\n# name: {}\n# key: {}\n# --\n{}",
+        name,
+        name,
+        expand_tabstops(&escape_yasnippet(content))
+    )
+}
+
+// Write `<dir>/rust-mode/<name>` for each snippet.
+pub fn write_yasnippet(snippets: &BTreeMap<String, String>, dir: &Path) -> io::Result<()> {
+    let dir = dir.join("rust-mode");
+    fs::create_dir_all(&dir)?;
+    for (name, content) in snippets.iter() {
+        fs::write(dir.join(snippet_file_name(name)), yasnippet(name, content))?;
+    }
+    Ok(())
+}
+
+// `]]>` can't be in CDATA, so split CDATA sections there.
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+// Sublime Text uses `$` and `\\` as escape characters like TextMate.
+fn sublime(name: &str, content: &str) -> String {
+    format!(
+        "<snippet>\n\
+         \x20   <content>{}</content>\n\
+         \x20   <tabTrigger>{}</tabTrigger>\n\
+         \x20   <scope>source.rust</scope>\n\
+         \x20   <description>{}</description>\n\
+         </snippet>\n",
+        cdata(&expand_tabstops(&escape_lsp(content))),
+        escape_xml(name),
+        escape_xml(name)
+    )
+}
+
+// Write `<dir>/<name>.sublime-snippet` for each snippet.
+pub fn write_sublime(snippets: &BTreeMap<String, String>, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, content) in snippets.iter() {
+        fs::write(
+            dir.join(format!("{}.sublime-snippet", snippet_file_name(name))),
+            sublime(name, content),
+        )?;
+    }
+    Ok(())
+}
+
+// Escape a string for an XML attribute value. Line breaks are kept as character references.
+fn escape_xml(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\n' => res.push_str("&#10;"),
+            '\r' => res.push_str("&#13;"),
+            '\t' => res.push_str("&#9;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// A placeholder is a `$name$` variable in JetBrains live templates and a literal `$` is `$$`.
+// Returns the template text and variables (name, default value) in the order of tabstops.
+fn jetbrains_template(content: &str) -> (String, Vec<(String, String)>) {
+    let mut text = String::new();
+    let mut variables = BTreeMap::new();
+    for segment in split(content.trim_end_matches('\n')) {
+        match segment {
+            Segment::Text(t) => text.push_str(&t.replace('$', "$$")),
+            Segment::Tabstop { number, name, .. } => {
+                let taken = variables.values().any(|(v, _): &(String, _)| v == name);
+                let (variable, _) = variables.entry(number).or_insert_with(|| {
+                    // Variables with the same name are the same
+                    let variable = if taken {
+                        format!("{}{}", name, number)
+                    } else {
+                        name.to_string()
+                    };
+                    (variable, name.to_string())
+                });
+                text += &format!("${}$", variable);
+            }
         }
     }
+    (text, variables.into_values().collect())
+}
+
+// A template set which can be imported to RustRover and IntelliJ Rust.
+fn jetbrains(snippets: &BTreeMap<String, String>) -> String {
+    let mut res = String::from("<templateSet group=\"cargo-snippet\">\n");
+    for (name, content) in snippets.iter() {
+        let (text, variables) = jetbrains_template(content);
+        res += &format!(
+            "  <template name=\"{}\" value=\"{}\" description=\"{}\" toReformat=\"false\" toShortenFQNames=\"false\">\n",
+            escape_xml(name),
+            escape_xml(&text),
+            escape_xml(name)
+        );
+        for (variable, default) in variables {
+            res += &format!(
+                "    <variable name=\"{}\" expression=\"\" defaultValue=\"{}\" alwaysStopAt=\"true\" />\n",
+                escape_xml(&variable),
+                // `defaultValue` is an expression
+                escape_xml(&format!("\"{}\"", default))
+            );
+        }
+        res +=
+            "    <context>\n      <option name=\"RUST_FILE\" value=\"true\" />\n    </context>\n";
+        res += "  </template>\n";
+    }
+    res += "</templateSet>\n";
+    res
+}
+
+pub fn write_jetbrains(snippets: &BTreeMap<String, String>) {
+    print!("{}", jetbrains(snippets));
 }
 
 #[test]
@@ -117,3 +439,245 @@ fn test_format_src() {
         Some("/// doc comment\npub fn foo() {}\n".into())
     );
 }
+
+#[test]
+fn test_format_statements() {
+    let mut snippets = BTreeMap::new();
+    snippets.insert(
+        "zeros".to_string(),
+        "fn zero() -> u64 { 0 } let n = 3 ; let v = vec ! [zero() ; n] ;".to_string(),
+    );
+    assert_eq!(
+        format_snippets(&snippets, &BTreeSet::new())["zeros"],
+        "fn zero() -> u64 {\n    0\n}\nlet n = 3;\nlet v = vec![zero(); n];\n"
+    );
+}
+
+#[test]
+fn test_yasnippet() {
+    assert_eq!(
+        yasnippet(
+            "fmt",
+            "fn f(__snip_1_n: u64) -> String {\n    format!(\"${}\\n`\", __snip_1_n)\n}\n"
+        ),
+        "# -*- mode: snippet -*-\n\
+         # name: fmt\n\
+         # key: fmt\n\
+         # --\n\
+         fn f(${1:n}: u64) -> String {\n    format!(\"\\${}\\\\n\\`\", $1)\n}\n"
+    );
+    assert_eq!(snippet_file_name("io/read"), "io_read");
+}
+
+#[test]
+fn test_write_yasnippet() {
+    use std::env;
+
+    let dir = env::temp_dir().join(format!("cargo-snippet-yasnippet-{}", std::process::id()));
+    let mut snippets = BTreeMap::new();
+    snippets.insert("io/read".to_string(), "read()\n".to_string());
+    snippets.insert("a\\b".to_string(), String::new());
+    write_yasnippet(&snippets, &dir).unwrap();
+    let read = fs::read_to_string(dir.join("rust-mode").join("io_read"));
+    let empty = fs::read_to_string(dir.join("rust-mode").join("a_b"));
+    // The directory can't be created under a file.
+    let file = dir.join("file");
+    fs::write(&file, "").unwrap();
+    let err = write_yasnippet(&snippets, &file);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        read.unwrap(),
+        "# -*- mode: snippet -*-\n# name: io/read\n# key: io/read\n# --\nread()\n"
+    );
+    assert!(empty.unwrap().ends_with("# --\n"));
+    assert!(err.is_err());
+}
+
+#[test]
+fn test_format_snippets_verbatim() {
+    use crate::parser::{parse_snippet, ParseOptions};
+    use crate::snippet::{process_snippets, verbatim_names};
+
+    let src = r#"
+#[snippet(verbatim)]
+fn modpow(a: u64,   n: u64) -> u64 {
+    // O(log n)
+    a.pow(n as u32)
+}
+
+#[snippet(include = "modpow")]
+fn sq(a: u64) -> u64 { modpow(a, 2) }
+"#;
+    let snips = parse_snippet(src, None, &ParseOptions::default()).0;
+    let verbatim = verbatim_names(&snips);
+    assert_eq!(verbatim.iter().collect::<Vec<_>>(), vec!["modpow"]);
+
+    let formatted = format_snippets(&process_snippets(&snips, &BTreeMap::new()), &verbatim);
+    assert_eq!(
+        formatted["modpow"],
+        "fn modpow(a: u64,   n: u64) -> u64 {\n    // O(log n)\n    a.pow(n as u32)\n}\n"
+    );
+    // Only the snippet with the attribute is left as is.
+    assert_eq!(
+        formatted["sq"],
+        "fn modpow(a: u64, n: u64) -> u64 {\n    // O(log n)\n    a.pow(n as u32)\n}\nfn sq(a: u64) -> u64 {\n    modpow(a, 2)\n}\n"
+    );
+}
+
+#[test]
+fn test_luasnip() {
+    let mut snippets = BTreeMap::new();
+    snippets.insert(
+        "zeros".to_string(),
+        "let v = vec![0; __snip_1_n];\nlet s = \"\\t\";\nassert_eq!(v.len(), __snip_1_n);\n"
+            .to_string(),
+    );
+    assert_eq!(
+        luasnip(&snippets),
+        "local ls = require(\"luasnip\")\n\
+         local s = ls.snippet\n\
+         local t = ls.text_node\n\
+         local i = ls.insert_node\n\
+         local rep = require(\"luasnip.extras\").rep\n\
+         \n\
+         return {\n\
+         \x20   s(\"zeros\", {\n\
+         \x20       t(\"let v = vec![0; \"),\n\
+         \x20       i(1, \"n\"),\n\
+         \x20       t({ \"];\", \"let s = \\\"\\\\t\\\";\", \"assert_eq!(v.len(), \" }),\n\
+         \x20       rep(1),\n\
+         \x20       t(\");\"),\n\
+         \x20   }),\n\
+         }\n"
+    );
+}
+
+#[test]
+fn test_luasnip_nodes() {
+    assert!(luasnip_nodes("").is_empty());
+    assert!(luasnip_nodes("\n").is_empty());
+    assert_eq!(luasnip_nodes("__snip_1_n\n"), vec!["i(1, \"n\")"]);
+    assert_eq!(luasnip_nodes("a\n\nb\n"), vec!["t({ \"a\", \"\", \"b\" })"]);
+    assert_eq!(
+        luasnip_nodes("__snip_1_n+__snip_1_n"),
+        vec!["i(1, \"n\")", "t(\"+\")", "rep(1)"]
+    );
+    assert_eq!(lua_string("a\r\"\\"), "\"a\\r\\\"\\\\\"");
+}
+
+#[test]
+fn test_vsnip() {
+    let mut snippets = BTreeMap::new();
+    snippets.insert(
+        "dollar".to_string(),
+        "println!(\"$\\n{}\", __snip_1_x);\n".to_string(),
+    );
+    snippets.insert("empty".to_string(), String::new());
+    let vsnip = vsnip(&snippets);
+    assert_eq!(vsnip["dollar"].prefix, vec!["dollar"]);
+    assert_eq!(
+        vsnip["dollar"].body,
+        vec!["println!(\"\\$\\\\n{}\", ${1:x});"]
+    );
+    assert!(vsnip["empty"].body.is_empty());
+}
+
+#[test]
+fn test_jetbrains() {
+    let mut snippets = BTreeMap::new();
+    snippets.insert(
+        "mod_pow".to_string(),
+        "let m = __snip_1_m;\nlet s = \"$<&>\";\nassert!(__snip_2_m > __snip_1_m);\n".to_string(),
+    );
+    assert_eq!(
+        jetbrains(&snippets),
+        r#"<templateSet group="cargo-snippet">
+  <template name="mod_pow" value="let m = $m$;&#10;let s = &quot;$$&lt;&amp;&gt;&quot;;&#10;assert!($m2$ &gt; $m$);" description="mod_pow" toReformat="false" toShortenFQNames="false">
+    <variable name="m" expression="" defaultValue="&quot;m&quot;" alwaysStopAt="true" />
+    <variable name="m2" expression="" defaultValue="&quot;m&quot;" alwaysStopAt="true" />
+    <context>
+      <option name="RUST_FILE" value="true" />
+    </context>
+  </template>
+</templateSet>
+"#
+    );
+}
+
+#[test]
+fn test_jetbrains_template() {
+    assert_eq!(
+        jetbrains_template("let s = \"$$\";\n"),
+        ("let s = \"$$$$\";".to_string(), Vec::new())
+    );
+    // A placeholder named like a generated variable is renamed as well.
+    let (text, variables) =
+        jetbrains_template("f(__snip_1_m, __snip_2_m, __snip_3_m2);\ng(__snip_2_m);");
+    assert_eq!(text, "f($m$, $m2$, $m23$);\ng($m2$);");
+    assert_eq!(
+        variables,
+        vec![
+            ("m".to_string(), "m".to_string()),
+            ("m2".to_string(), "m".to_string()),
+            ("m23".to_string(), "m2".to_string()),
+        ]
+    );
+    assert_eq!(escape_xml("a\r\n\tb'"), "a&#13;&#10;&#9;b'");
+}
+
+#[test]
+fn test_sublime() {
+    assert_eq!(
+        sublime(
+            "a<b",
+            "let v: Vec<Vec<u8>> = vec![vec![__snip_1_x]];\nlet w = v[v[0]]>0;\n"
+        ),
+        "<snippet>\n\
+         \x20   <content><![CDATA[let v: Vec<Vec<u8>> = vec![vec![${1:x}]];\nlet w = v[v[0]]]]><![CDATA[>0;\n]]></content>\n\
+         \x20   <tabTrigger>a&lt;b</tabTrigger>\n\
+         \x20   <scope>source.rust</scope>\n\
+         \x20   <description>a&lt;b</description>\n\
+         </snippet>\n"
+    );
+}
+
+#[test]
+fn test_write_sublime() {
+    use std::env;
+
+    let dir = env::temp_dir().join(format!("cargo-snippet-sublime-{}", std::process::id()));
+    let mut snippets = BTreeMap::new();
+    snippets.insert("io/read".to_string(), "read()\n".to_string());
+    write_sublime(&snippets, &dir).unwrap();
+    let read = fs::read_to_string(dir.join("io_read.sublime-snippet"));
+    let file = dir.join("file");
+    fs::write(&file, "").unwrap();
+    let err = write_sublime(&snippets, &file);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(read.unwrap().contains("<tabTrigger>io/read</tabTrigger>"));
+    assert!(err.is_err());
+    assert_eq!(cdata(""), "<![CDATA[]]>");
+    assert_eq!(
+        cdata("]]>]]>"),
+        "<![CDATA[]]]]><![CDATA[>]]]]><![CDATA[>]]>"
+    );
+}
+
+#[test]
+fn test_zed() {
+    let mut snippets = BTreeMap::new();
+    snippets.insert(
+        "dollar".to_string(),
+        "let a = __snip_1_x;\nprintln!(\"$\\n{}\", __snip_1_x);\n".to_string(),
+    );
+    snippets.insert("empty".to_string(), "\n\n".to_string());
+    let zed = zed(&snippets);
+    assert_eq!(zed["dollar"].prefix, "dollar");
+    assert_eq!(
+        zed["dollar"].body,
+        "let a = ${1:x};\nprintln!(\"\\$\\\\n{}\", $1);"
+    );
+    assert_eq!(zed["empty"].body, "");
+}